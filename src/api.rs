@@ -0,0 +1,312 @@
+// Local http api for editor plugins and dashboards
+// Loopback only , every request needs the token from the config
+//   Authorization: Bearer <token>    ( or ?token=<token> , EventSource can't set headers )
+//   GET    /notes         ids of the notes in the current doc set
+//   GET    /notes/<id>    one note as json
+//   POST   /notes/<id>    new note , the body is the text , 409 if it is there already
+//   PUT    /notes/<id>    create or replace
+//   DELETE /notes/<id>    hide
+//   GET    /events        server sent events , one "change" per remote edit
+// The handlers don't touch the notes , they ask the worker
+// so indexing , the mirror and read only docs all behave like the gui.
+
+use std::convert::Infallible;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_channel::{Sender, TrySendError};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    response::{
+        IntoResponse, Response,
+        sse::{Event as SseEvent, KeepAlive, Sse},
+    },
+    routing::get,
+};
+use n0_future::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::comms::Command;
+use crate::notes::normalize_path;
+
+// changes queued for a slow event listener before they are dropped
+const EVENT_BACKLOG: usize = 64;
+
+// What a handler wants from the worker
+pub enum ApiCall {
+    List,
+    Get(String),
+    Create(String, String),
+    Put(String, String),
+    Hide(String),
+}
+
+// Failed call , status code and why
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+// anything unexpected is our problem , bad requests say so themselves
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::new(500, err.to_string())
+    }
+}
+
+// A reply body , a value that won't turn into json is a bug here
+pub fn to_json<T: Serialize>(value: &T) -> Result<Value, ApiError> {
+    serde_json::to_value(value).map_err(|e| ApiError::from(anyhow::Error::from(e)))
+}
+
+// The id out of the url , 400 if there is nothing left of it
+pub fn note_id(id: &str) -> Result<String, ApiError> {
+    let id = normalize_path(id);
+    if id.is_empty() {
+        return Err(ApiError::new(400, "empty note id"));
+    }
+    Ok(id)
+}
+
+pub type ApiReply = oneshot::Sender<Result<Value, ApiError>>;
+
+// Open event streams
+type Listeners = Arc<std::sync::Mutex<Vec<Sender<Value>>>>;
+
+#[derive(Clone)]
+struct ApiState {
+    command_tx: Sender<Command>,
+    token: String,
+    listeners: Listeners,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+// A running server , dropping it stops it.
+pub struct Api {
+    _stop: Sender<()>,
+    listeners: Listeners,
+    pub port: u16,
+    pub token: String,
+}
+
+impl Api {
+    // Bind and hand back the server future for the task pool
+    pub async fn start(
+        port: u16,
+        token: String,
+        command_tx: Sender<Command>,
+    ) -> Result<(Self, n0_future::boxed::BoxFuture<()>)> {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+        info!("api listening on {}", listener.local_addr()?);
+        let listeners: Listeners = Default::default();
+        let state = ApiState {
+            command_tx,
+            token: token.clone(),
+            listeners: listeners.clone(),
+        };
+        let app = Router::new()
+            .route("/notes", get(list))
+            .route(
+                "/notes/{*id}",
+                get(get_note).post(create).put(put).delete(hide),
+            )
+            .route("/events", get(events))
+            .with_state(state);
+        let (stop, stopped) = async_channel::bounded::<()>(1);
+        let server = async move {
+            let shutdown = async move {
+                let _ = stopped.recv().await;
+            };
+            if let Err(e) = axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+            {
+                warn!("api stopped , {e}");
+            }
+            info!("api stopped");
+        };
+        let api = Self {
+            _stop: stop,
+            listeners,
+            port,
+            token,
+        };
+        Ok((api, Box::pin(server)))
+    }
+
+    // Tell every event stream , closed ones drop out
+    pub fn notify(&self, change: Value) {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.retain(|l| !matches!(l.try_send(change.clone()), Err(TrySendError::Closed(_))));
+        }
+    }
+}
+
+// The event streams only end when their senders go ,
+// the graceful shutdown waits on them otherwise.
+impl Drop for Api {
+    fn drop(&mut self) {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.clear();
+        }
+    }
+}
+
+// A fresh random token for the config
+pub fn new_token() -> String {
+    data_encoding::HEXLOWER.encode(&rand::random::<[u8; 16]>())
+}
+
+fn authorized(state: &ApiState, headers: &HeaderMap, query: &TokenQuery) -> bool {
+    if state.token.is_empty() {
+        return false;
+    }
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    match bearer.or(query.token.as_deref()) {
+        Some(token) => same_token(token, &state.token),
+        None => false,
+    }
+}
+
+// Compare every byte whatever happens , so the time taken
+// doesn't give away how much of a guess was right.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Hand the call to the worker and wait for its answer
+async fn call(state: &ApiState, headers: &HeaderMap, query: &TokenQuery, call: ApiCall) -> Response {
+    if !authorized(state, headers, query) {
+        return error(ApiError::new(401, "bad or missing token"));
+    }
+    let (reply, answer) = oneshot::channel();
+    if state.command_tx.send(Command::Api(call, reply)).await.is_err() {
+        return error(ApiError::new(503, "worker is not running"));
+    }
+    match answer.await {
+        Ok(Ok(value)) => Json(value).into_response(),
+        Ok(Err(err)) => error(err),
+        Err(_) => error(ApiError::new(500, "worker dropped the call")),
+    }
+}
+
+fn error(err: ApiError) -> Response {
+    let status = StatusCode::from_u16(err.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, Json(json!({ "error": err.message }))).into_response()
+}
+
+async fn list(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    call(&state, &headers, &query, ApiCall::List).await
+}
+
+async fn get_note(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    Path(id): Path<String>,
+) -> Response {
+    call(&state, &headers, &query, ApiCall::Get(id)).await
+}
+
+async fn create(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    Path(id): Path<String>,
+    text: String,
+) -> Response {
+    call(&state, &headers, &query, ApiCall::Create(id, text)).await
+}
+
+async fn put(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    Path(id): Path<String>,
+    text: String,
+) -> Response {
+    call(&state, &headers, &query, ApiCall::Put(id, text)).await
+}
+
+async fn hide(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    Path(id): Path<String>,
+) -> Response {
+    call(&state, &headers, &query, ApiCall::Hide(id)).await
+}
+
+// Remote changes as they happen
+async fn events(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if !authorized(&state, &headers, &query) {
+        return error(ApiError::new(401, "bad or missing token"));
+    }
+    let (tx, rx) = async_channel::bounded(EVENT_BACKLOG);
+    if let Ok(mut listeners) = state.listeners.lock() {
+        listeners.push(tx);
+    }
+    let stream = rx.map(|change: Value| {
+        Ok::<_, Infallible>(SseEvent::default().event("change").data(change.to_string()))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_match_exactly() {
+        assert!(same_token("abc123", "abc123"));
+        assert!(!same_token("abc124", "abc123"));
+        assert!(!same_token("abc", "abc123"));
+        assert!(!same_token("", "abc123"));
+    }
+
+    #[test]
+    fn empty_ids_are_bad_requests() {
+        assert_eq!(note_id(" a / b ").ok().as_deref(), Some("a/b"));
+        assert_eq!(note_id("/ /").err().map(|e| e.status), Some(400));
+    }
+
+    #[test]
+    fn internal_errors_are_500() {
+        assert_eq!(ApiError::from(anyhow::anyhow!("store broke")).status, 500);
+    }
+}
@@ -1,26 +1,35 @@
 // The application egui front end
 
 use core::f32;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Display;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use crate::about::ABOUT;
+use crate::attach::{image_urls, parse_blob_url};
 use crate::comms::{Command, Config, Event, MessageDisplay, MessageType};
-use crate::notes::Note;
+use crate::diff::{CONTEXT, DiffLine, NoteChange, with_context};
+use crate::notes::{Note, NoteKind, NoteVersion, Revision, Snapshot, Trashed, normalize_path};
+use crate::links::{link_target, render_links};
+use crate::search::SearchHit;
+use crate::sync::{SyncState, SyncStatus};
 use crate::worker::{Worker, WorkerHandle};
 
 use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
 use directories::{BaseDirs, UserDirs};
 use eframe::NativeOptions;
-use eframe::egui::{self, FontId, RichText, Visuals};
+use eframe::egui::{self, FontId, OutputCommand, RichText, Visuals};
 use egui::Ui;
 use egui_commonmark::{CommonMarkCache, CommonMarkViewer};
 use iroh::SecretKey;
+use iroh_blobs::Hash;
 use rfd::FileDialog;
 
 use tracing::{info, warn};
 
-const APP_NAME: &str = "liminal-docs";
+pub const APP_NAME: &str = "liminal-docs";
 
 // The starter config,
 impl Default for Config {
@@ -51,12 +60,22 @@ impl Default for Config {
             doc_key: None,
             author: None,
             mothership: None,
+            doc_sets: Vec::new(),
+            last_exports: BTreeMap::new(),
+            snapshot_minutes: 60,
+            mirror: false,
+            trash_days: 30,
+            api: false,
+            api_port: 7733,
+            api_token: String::new(),
         }
     }
 }
 
 // Message list max (control the logs)
 const MESSAGE_MAX: usize = 3;
+// ask again for an image that has not turned up after this long
+const ATTACHMENT_RETRY: Duration = Duration::from_secs(30);
 
 // The application overlord
 pub struct App {
@@ -66,13 +85,26 @@ pub struct App {
 
 // The application mode
 // this controls the app state and display
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 enum AppMode {
     Init,
     Ready,
     Idle,
     Edit,
     NewNote,
+    History,
+    Conflict,
+    MoveFolder,
+    RenameNote,
+    Search,
+    Snapshots,
+    Diff,
+    Import,
+    Trash,
+    Sync,
+    ConfirmDeleteHidden,
+    LeaveDoc,
+    NewDoc,
     GetDocTicket,
     ShareTicket,
     Finished,
@@ -89,6 +121,19 @@ impl Display for AppMode {
             AppMode::Idle => "Idle",
             AppMode::Edit => "Editing ...",
             AppMode::NewNote => "NewNote ...",
+            AppMode::History => "History ...",
+            AppMode::Conflict => "Conflict ...",
+            AppMode::MoveFolder => "Move Folder ...",
+            AppMode::RenameNote => "Rename ...",
+            AppMode::Search => "Search ...",
+            AppMode::Snapshots => "Snapshots ...",
+            AppMode::Diff => "Diff ...",
+            AppMode::Import => "Import ...",
+            AppMode::Trash => "Trash ...",
+            AppMode::Sync => "Sync ...",
+            AppMode::ConfirmDeleteHidden => "Delete Hidden ...",
+            AppMode::LeaveDoc => "Leave Doc ...",
+            AppMode::NewDoc => "New Doc Set ...",
             AppMode::Finished => "Finished",
             AppMode::Config => "Config",
             AppMode::About => "About...",
@@ -108,6 +153,8 @@ struct AppState {
     worker: WorkerHandle,
     mode: AppMode,
     receiver_ticket: String,
+    // name for a doc set being joined
+    doc_name: String,
     current_note: Option<Note>,
     current_text: String,
     backup_text: String,
@@ -115,8 +162,33 @@ struct AppState {
     config: Config,
     elapsed: Option<u64>,
     share_ticket: Option<String>,
+    // write tickets only when asked for
+    share_write: bool,
+    // sync supervisor status by doc key
+    sync: HashMap<String, SyncStatus>,
     cache: CommonMarkCache,
     new_note_name: String,
+    history: Vec<Revision>,
+    revision: Option<(Hash, Note)>,
+    versions: Option<(String, Vec<NoteVersion>)>,
+    merge_text: String,
+    folder_move: Option<(String, String)>,
+    rename_to: String,
+    search_query: String,
+    search_results: Vec<SearchHit>,
+    backlinks: Option<(String, Vec<String>)>,
+    // image attachments , when they were asked for , None once they are here
+    attachments: HashMap<String, Option<Instant>>,
+    snapshots: Vec<Snapshot>,
+    snapshot: Option<(Hash, Vec<Note>)>,
+    snapshot_picks: BTreeSet<String>,
+    snapshot_preview: Option<Note>,
+    diff: Option<(String, Vec<NoteChange>)>,
+    // where the diff view goes back to
+    diff_back: AppMode,
+    import_path: Option<PathBuf>,
+    import_overwrite: bool,
+    trash: Vec<Trashed>,
 }
 
 // Make the egui impl for display
@@ -127,6 +199,8 @@ impl eframe::App for App {
         if self.is_first_update {
             self.is_first_update = false;
             ctx.set_zoom_factor(1.);
+            // inline images in the viewer
+            egui_extras::install_image_loaders(ctx);
             if self.state.config.dark_mode {
                 ctx.set_visuals(Visuals::dark());
             } else {
@@ -165,9 +239,31 @@ impl App {
             config: config,
             elapsed: None,
             share_ticket: None,
+            share_write: false,
+            sync: HashMap::new(),
             cache: CommonMarkCache::default(),
             receiver_ticket: String::new(),
+            doc_name: String::new(),
             new_note_name: String::new(),
+            history: Vec::new(),
+            revision: None,
+            versions: None,
+            merge_text: String::new(),
+            folder_move: None,
+            rename_to: String::new(),
+            search_query: String::new(),
+            search_results: Vec::new(),
+            backlinks: None,
+            attachments: HashMap::new(),
+            snapshots: Vec::new(),
+            snapshot: None,
+            snapshot_picks: BTreeSet::new(),
+            snapshot_preview: None,
+            diff: None,
+            diff_back: AppMode::Idle,
+            import_path: None,
+            import_overwrite: false,
+            trash: Vec::new(),
         };
 
         // New App
@@ -213,17 +309,92 @@ impl AppState {
                 Event::NoteList(list) => {
                     self.notes.update(list);
                 }
+                Event::Conflicts(conflicts) => {
+                    self.notes.set_conflicts(conflicts);
+                }
+                Event::NoteConflict(id, versions) => {
+                    self.versions = Some((id, versions));
+                }
                 Event::SendNote(note) => {
+                    // conflict versions follow the note if there are any
+                    self.versions = None;
                     self.notes.set(note.clone());
                     self.current_note = Some(note);
                     // self.current_note = Some(note.clone());
                 }
+                Event::NoteHistory(id, revisions) => {
+                    // only if it is still the note we are looking at
+                    if let Some(note) = &self.current_note {
+                        if note.id == id {
+                            self.history = revisions;
+                            self.revision = None;
+                        }
+                    }
+                }
+                Event::SendRevision(hash, note) => {
+                    self.revision = Some((hash, note));
+                }
+                Event::SearchResults(query, hits) => {
+                    // drop stale results
+                    if query == self.search_query {
+                        self.search_results = hits;
+                    }
+                }
+                Event::Backlinks(id, backlinks) => {
+                    self.backlinks = Some((id, backlinks));
+                }
+                Event::Attached(markdown) => {
+                    self.attach_markdown(markdown);
+                }
+                Event::Attachment(url, bytes) => {
+                    // hand the bytes to the image loader under the blob: url
+                    self.attachments.insert(url.clone(), None);
+                    ctx.forget_image(&url);
+                    ctx.include_bytes(url, bytes.to_vec());
+                }
+                Event::Snapshots(snapshots) => {
+                    self.snapshots = snapshots;
+                }
+                Event::Trash(trash) => {
+                    self.trash = trash;
+                }
+                Event::SnapshotNotes(hash, notes) => {
+                    self.snapshot = Some((hash, notes));
+                    self.snapshot_picks.clear();
+                    self.snapshot_preview = None;
+                }
+                Event::Diff(title, changes) => {
+                    self.diff = Some((title, changes));
+                    self.mode = AppMode::Diff;
+                }
+                Event::RemoteChange(id) => {
+                    // refresh if we are looking at it (not while editing)
+                    if let Some(note) = &self.current_note {
+                        if note.id == id && self.mode == AppMode::Idle {
+                            self.cmd(Command::GetNote(id));
+                        }
+                    }
+                }
                 Event::SendShareTicket(share_ticket) => {
                     self.share_ticket = Some(share_ticket);
                 }
                 Event::SetReady => {
                     self.mode = AppMode::Ready;
                 }
+                Event::SyncStatus(key, status) => {
+                    self.sync.insert(key, status);
+                }
+                Event::Stopped | Event::Pong => {}
+            }
+        }
+
+        // files dropped on the window get attached to the current note
+        let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+        for file in dropped {
+            if let Some(path) = file.path {
+                if self.current_note.is_some() {
+                    self.cmd(Command::AttachFile(path));
+                }
             }
         }
 
@@ -283,41 +454,173 @@ impl AppState {
             .show(ctx, |ui| {
                 // needs to be scrolly.
                 ui.add_space(10.);
+                ui.horizontal(|ui| {
+                    let search = egui::TextEdit::singleline(&mut self.search_query)
+                        .hint_text("Search")
+                        .desired_width(100.);
+                    let response = ui.add(search);
+                    let enter =
+                        response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if (ui.button("Find").clicked() || enter) && !self.search_query.is_empty() {
+                        self.search_results = Vec::new();
+                        self.cmd(Command::Search(self.search_query.clone()));
+                        self.mode = AppMode::Search;
+                    }
+                });
+                ui.add_space(5.);
                 ui.strong("Notes");
                 ui.add_space(1.);
-                ui.horizontal(|ui| {
-                    let name =
-                        egui::TextEdit::singleline(&mut self.new_note_name).desired_width(100.);
-                    ui.add(name);
-
-                    if ui.button("New").clicked() {
-                        if !self.new_note_name.is_empty() {
-                            warn!("make new note : {}", self.new_note_name);
-                            let id = self
-                                .new_note_name
-                                .clone()
-                                .chars()
-                                .filter(|c| c.is_ascii_alphanumeric() || c.is_whitespace())
-                                .collect();
-                            self.current_note = Some(Note::missing_note(id));
-                            self.current_text = String::new();
-                            self.new_note_name = String::new();
-                            self.mode = AppMode::NewNote;
-                            if let Some(note) = &self.current_note {
-                                println!("{:#?}", note);
+                if self.read_only() {
+                    ui.label(RichText::new("Read only doc set").color(egui::Color32::LIGHT_RED));
+                    ui.small("Joined with a read ticket , notes can be looked at but not changed.");
+                } else {
+                    ui.horizontal(|ui| {
+                        let name =
+                            egui::TextEdit::singleline(&mut self.new_note_name).desired_width(100.);
+                        ui.add(name);
+
+                        if ui.button("New").clicked() {
+                            if !self.new_note_name.is_empty() {
+                                warn!("make new note : {}", self.new_note_name);
+                                // slashes make folders
+                                let id: String = self
+                                    .new_note_name
+                                    .clone()
+                                    .chars()
+                                    .filter(|c| {
+                                        c.is_ascii_alphanumeric() || c.is_whitespace() || *c == '/'
+                                    })
+                                    .collect();
+                                let id = normalize_path(&id);
+                                self.current_note = Some(Note::missing_note(id));
+                                self.current_text = String::new();
+                                self.new_note_name = String::new();
+                                self.mode = AppMode::NewNote;
+                                if let Some(note) = &self.current_note {
+                                    println!("{:#?}", note);
+                                }
                             }
                         }
+                    });
+                    if ui.small_button("New Folder").clicked() {
+                        let path = normalize_path(&self.new_note_name);
+                        if !path.is_empty() {
+                            self.cmd(Command::NewFolder(path));
+                            self.new_note_name = String::new();
+                        }
                     }
-                });
+                }
                 ui.separator();
                 ui.add_space(1.);
 
                 if let Some(name) = self.notes.show(ui) {
                     self.cmd(Command::GetNote(name));
                 }
+                if let Some(folder) = self.notes.take_move_request() {
+                    self.folder_move = Some((folder.clone(), folder));
+                    self.mode = AppMode::MoveFolder;
+                }
             });
     }
 
+    // Sync status of the shown doc set , click for the details
+    fn sync_label(&mut self, ctx: &egui::Context, ui: &mut Ui) {
+        let Some(status) = self.current_sync() else {
+            return;
+        };
+        let color = match status.state {
+            SyncState::Synced => egui::Color32::GREEN,
+            SyncState::BackingOff { .. } => egui::Color32::ORANGE,
+            SyncState::Connecting | SyncState::Waiting => ui.visuals().weak_text_color(),
+        };
+        // keep the countdown ( and the "ago" ) moving
+        let repaint = match status.state {
+            SyncState::BackingOff { .. } => 1,
+            _ => 30,
+        };
+        ctx.request_repaint_after(Duration::from_secs(repaint));
+        let mut text = format!("{} , {} peers", status.state, status.connected());
+        if let Some(last) = status.last_sync() {
+            text.push_str(&format!(" , last sync {}", format_ago(last)));
+        }
+        if status.pending > 0 {
+            text.push_str(&format!(" , {} pending", status.pending));
+        }
+        let label = egui::Label::new(RichText::new(text).color(color)).sense(egui::Sense::click());
+        if ui
+            .add(label)
+            .on_hover_text("sync details")
+            .clicked()
+        {
+            self.mode = AppMode::Sync;
+        }
+        ui.separator();
+    }
+
+    fn current_sync(&self) -> Option<SyncStatus> {
+        let key = self.config.doc_key.as_ref()?;
+        self.sync.get(key).cloned()
+    }
+
+    // Sync detail panel , every peer this doc has seen
+    fn show_sync(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.strong("Sync");
+            ui.add_space(20.);
+            if ui.button("Back").clicked() {
+                self.mode = AppMode::Idle;
+            }
+            if let Some(key) = self.config.doc_key.clone() {
+                if ui
+                    .button("Reconnect Now")
+                    .on_hover_text("start again without waiting for the backoff")
+                    .clicked()
+                {
+                    self.cmd(Command::Attach(key));
+                }
+            }
+        });
+        ui.separator();
+        let Some(status) = self.current_sync() else {
+            ui.label("Not syncing yet.");
+            return;
+        };
+        ui.label(format!("State: {}", status.state));
+        ui.label(format!("Connected peers: {}", status.connected()));
+        ui.label(format!("Entries waiting for content: {}", status.pending));
+        ui.separator();
+        if status.peers.is_empty() {
+            ui.label("No peers seen yet.");
+            return;
+        }
+        egui::ScrollArea::vertical().id_salt("sync").show(ui, |ui| {
+            egui::Grid::new("sync grid").striped(true).show(ui, |ui| {
+                ui.strong("Peer");
+                ui.strong("Connected");
+                ui.strong("Last sync");
+                ui.strong("Last error");
+                ui.end_row();
+                for (peer, sync) in &status.peers {
+                    ui.monospace(peer);
+                    ui.label(if sync.connected { "yes" } else { "no" });
+                    match sync.last_sync {
+                        Some(last) => ui.label(format!(
+                            "{} ({})",
+                            format_timestamp(last),
+                            format_ago(last)
+                        )),
+                        None => ui.label("never"),
+                    };
+                    match &sync.last_error {
+                        Some(err) => ui.colored_label(egui::Color32::LIGHT_RED, err),
+                        None => ui.label(""),
+                    };
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
     // Status bar footer
     fn footer(&mut self, ctx: &egui::Context) {
         // Status bar at the bottom
@@ -339,6 +642,7 @@ impl AppState {
                         ui.label(RichText::new(format_seconds_as_hms(elapsed_seconds)).strong());
                     }
                     ui.label(format!(" {} ", self.mode));
+                    self.sync_label(ctx, ui);
                 });
             });
             ui.add_space(5.);
@@ -350,13 +654,31 @@ impl AppState {
         ui.horizontal(|ui| {
             ui.add_space(2.);
             ui.add_enabled_ui(send_enabled, |ui| {
+                self.doc_switcher(ui);
                 if ui.button("List Notes").clicked() {
                     self.cmd(Command::GetNotes);
                 };
                 if ui.button("Share...").clicked() {
-                    self.cmd(Command::GetShareTicket);
+                    self.share_ticket = None;
+                    self.share_write = false;
+                    self.cmd(Command::GetShareTicket(false));
                     self.mode = AppMode::ShareTicket;
                 }
+                if ui.button("Export").clicked() {
+                    self.cmd(Command::Export(None));
+                }
+                if !self.read_only() && ui.button("Import...").clicked() {
+                    if let Some(path) = FileDialog::new().pick_folder() {
+                        self.import_path = Some(path);
+                        self.import_overwrite = false;
+                        self.mode = AppMode::Import;
+                    }
+                }
+                if ui.button("Snapshots").clicked() {
+                    self.snapshot = None;
+                    self.cmd(Command::GetSnapshots);
+                    self.mode = AppMode::Snapshots;
+                }
                 ui.add_space(20.);
                 if ui.button("About").clicked() {
                     self.mode = AppMode::About;
@@ -364,8 +686,26 @@ impl AppState {
                 if ui.button("Config").clicked() {
                     self.mode = AppMode::Config;
                 }
-                if ui.button("Delete Hidden").clicked() {
-                    self.cmd(Command::DeleteHidden);
+                if ui.button("Trash").clicked() {
+                    self.cmd(Command::GetTrash);
+                    self.mode = AppMode::Trash;
+                }
+                if !self.read_only() && ui.button("Delete Hidden").clicked() {
+                    self.cmd(Command::GetTrash);
+                    self.mode = AppMode::ConfirmDeleteHidden;
+                }
+                ui.add_space(20.);
+                if ui.button("New Doc Set...").clicked() {
+                    self.doc_name.clear();
+                    self.mode = AppMode::NewDoc;
+                }
+                if ui.button("Join Doc...").clicked() {
+                    self.receiver_ticket.clear();
+                    self.doc_name.clear();
+                    self.mode = AppMode::GetDocTicket;
+                }
+                if ui.button("Leave Doc").clicked() {
+                    self.mode = AppMode::LeaveDoc;
                 }
             });
             ui.add_space(5.);
@@ -381,17 +721,65 @@ impl AppState {
                 if let Some(current_note) = &self.current_note {
                     let viewer = CommonMarkViewer::new();
                     let current_note = current_note.clone();
+                    // followed a link to a note that is not there (yet)
+                    if current_note.is_missing() {
+                        self.missing_note(&current_note, ui);
+                        return;
+                    }
                     ui.vertical(|ui| {
-                        ui.strong(&current_note.id);
+                        ui.horizontal(|ui| {
+                            ui.strong(&current_note.id);
+                            if current_note.kind == NoteKind::Crdt {
+                                ui.small("(collaborative)");
+                            }
+                            if current_note.is_read_only() {
+                                ui.small("(read only)");
+                            }
+                        });
                         ui.separator();
+                        // placeholders from newer versions can only be looked at
+                        let writable = !current_note.is_read_only();
+                        // a read only doc does not even offer
+                        let doc_writable = !self.read_only();
                         ui.horizontal(|ui| {
-                            if ui.button("Edit").clicked() {
+                            if doc_writable
+                                && ui.add_enabled(writable, egui::Button::new("Edit")).clicked()
+                            {
                                 self.backup_text = current_note.text.clone();
                                 self.current_text = current_note.text.clone();
                                 self.mode = AppMode::Edit;
                             };
+                            if ui.button("History").clicked() {
+                                self.history = Vec::new();
+                                self.revision = None;
+                                self.cmd(Command::GetHistory(current_note.id.clone()));
+                                self.mode = AppMode::History;
+                            };
+                            if doc_writable
+                                && ui.add_enabled(writable, egui::Button::new("Rename")).clicked()
+                            {
+                                self.rename_to = current_note.id.clone();
+                                self.mode = AppMode::RenameNote;
+                            };
+                            if current_note.kind == NoteKind::Plain && doc_writable {
+                                if ui.button("Make Collaborative").clicked() {
+                                    self.cmd(Command::ConvertNote(current_note.id.clone()));
+                                }
+                            }
+                            if let Some((id, _)) = &self.versions {
+                                if *id == current_note.id {
+                                    let text = RichText::new("Resolve Conflict")
+                                        .color(egui::Color32::LIGHT_RED);
+                                    if ui.button(text).clicked() {
+                                        self.merge_text = current_note.text.clone();
+                                        self.mode = AppMode::Conflict;
+                                    }
+                                }
+                            }
                             ui.add_space(50.);
-                            if ui.button("Hide").clicked() {
+                            if doc_writable
+                                && ui.add_enabled(writable, egui::Button::new("Hide")).clicked()
+                            {
                                 let id = current_note.id.clone();
                                 self.cmd(Command::HideNote(id));
                                 self.cmd(Command::GetNotes);
@@ -400,12 +788,65 @@ impl AppState {
                             };
                         });
                         ui.separator();
-                        viewer.show_scrollable(
-                            "markdown",
-                            ui,
-                            &mut self.cache,
-                            &current_note.text.as_str(),
-                        );
+                        // ask the worker for any images we have not got
+                        // a failed fetch gets another go after a while
+                        for url in image_urls(&current_note.text) {
+                            let due = match self.attachments.get(&url) {
+                                None => true,
+                                Some(None) => false,
+                                Some(Some(asked)) => asked.elapsed() > ATTACHMENT_RETRY,
+                            };
+                            if due {
+                                self.attachments.insert(url.clone(), Some(Instant::now()));
+                                self.cmd(Command::GetAttachment(url));
+                            }
+                        }
+                        // [[links]] become note: urls
+                        let text = render_links(&current_note.text);
+                        let mut open = None;
+                        egui::ScrollArea::vertical()
+                            .id_salt("markdown")
+                            .show(ui, |ui| {
+                                viewer.show(ui, &mut self.cache, text.as_str());
+                                if let Some((id, backlinks)) = &self.backlinks {
+                                    if *id == current_note.id && !backlinks.is_empty() {
+                                        ui.separator();
+                                        ui.small("Linked from");
+                                        for from in backlinks.iter() {
+                                            if ui.link(from).clicked() {
+                                                open = Some(from.clone());
+                                            }
+                                        }
+                                    }
+                                }
+                            });
+                        // link clicks come out as open url commands , keep ours
+                        let (clicked, save) = ctx.output_mut(|o| {
+                            let mut target = None;
+                            let mut save = None;
+                            o.commands.retain(|c| match c {
+                                OutputCommand::OpenUrl(url) => {
+                                    if let Some(t) = link_target(&url.url) {
+                                        target = Some(t);
+                                        false
+                                    } else if parse_blob_url(&url.url).is_some() {
+                                        save = Some(url.url.clone());
+                                        false
+                                    } else {
+                                        true
+                                    }
+                                }
+                                _ => true,
+                            });
+                            (target, save)
+                        });
+                        if let Some(id) = open.or(clicked) {
+                            self.cmd(Command::GetNote(id));
+                        }
+                        // file attachments get saved to the download folder
+                        if let Some(url) = save {
+                            self.cmd(Command::SaveAttachment(url));
+                        }
                     });
                 };
             }
@@ -440,6 +881,12 @@ impl AppState {
                                 };
                             };
                             ui.add_space(10.);
+                            if ui.button("Attach").clicked() {
+                                if let Some(path) = FileDialog::new().pick_file() {
+                                    self.cmd(Command::AttachFile(path));
+                                }
+                            }
+                            ui.add_space(10.);
                             if ui.button("Cancel").clicked() {
                                 // put the saved text back into the current note
                                 self.current_text = self.backup_text.clone();
@@ -453,17 +900,46 @@ impl AppState {
                     });
                 }
             }
+            AppMode::History => self.show_history(ui),
+            AppMode::Conflict => self.show_conflict(ui),
+            AppMode::MoveFolder => self.show_move_folder(ui),
+            AppMode::RenameNote => self.show_rename(ui),
+            AppMode::Search => self.show_search(ui),
+            AppMode::Snapshots => self.show_snapshots(ui),
+            AppMode::Diff => self.show_diff(ui),
+            AppMode::Import => self.show_import(ui),
+            AppMode::Trash => self.show_trash(ui),
+            AppMode::Sync => self.show_sync(ui),
+            AppMode::ConfirmDeleteHidden => self.show_confirm_delete(ui),
+            AppMode::LeaveDoc => self.show_leave_doc(ui),
+            AppMode::NewDoc => self.show_new_doc(ui),
             AppMode::Finished => {}
             AppMode::Config => {
                 self.show_config(ctx,ui);
             }
             AppMode::About => self.about(ui),
-            AppMode::GetDocTicket => {
-                // TODO no way to get back here after initial
-                self.ticket_box(ui);
-            }
+            AppMode::GetDocTicket => self.ticket_box(ui),
             AppMode::ShareTicket => {
-                // TODO , currently only a RW ticked
+                // read for looking , write for editing
+                ui.horizontal(|ui| {
+                    ui.label("Access");
+                    let read = ui.radio_value(&mut self.share_write, false, "Read");
+                    let write = ui.add_enabled_ui(!self.read_only(), |ui| {
+                        ui.radio_value(&mut self.share_write, true, "Write")
+                    });
+                    if read.changed() || write.inner.changed() {
+                        self.share_ticket = None;
+                        self.cmd(Command::GetShareTicket(self.share_write));
+                    }
+                });
+                if self.share_write {
+                    ui.small("Anyone with this ticket can change and delete notes.");
+                } else {
+                    ui.small("This ticket can only look , not change anything.");
+                }
+                if self.share_ticket.is_none() {
+                    ui.label("Waiting for the ticket ...");
+                }
                 if let Some(ticket) = &self.share_ticket {
                     ui.add_space(10.);
                     ui.label("Doc Share Ticket...");
@@ -481,6 +957,681 @@ impl AppState {
         }
     }
 
+    // A file was stored , put the reference into the note
+    // straight into the editor , or start editing if we were just looking.
+    fn attach_markdown(&mut self, markdown: String) {
+        match self.mode {
+            AppMode::Edit | AppMode::NewNote => {}
+            _ => {
+                let Some(note) = &self.current_note else {
+                    return;
+                };
+                if note.is_missing() || note.is_read_only() {
+                    return;
+                }
+                self.backup_text = note.text.clone();
+                self.current_text = note.text.clone();
+                self.mode = AppMode::Edit;
+            }
+        }
+        if !self.current_text.is_empty() && !self.current_text.ends_with('\n') {
+            self.current_text.push('\n');
+        }
+        self.current_text.push_str(&markdown);
+        self.current_text.push('\n');
+    }
+
+    // A link pointed at a note that does not exist , offer to make it
+    fn missing_note(&mut self, note: &Note, ui: &mut Ui) {
+        ui.strong(&note.id);
+        ui.separator();
+        ui.label(format!("There is no note called \"{}\" yet.", &note.id));
+        ui.add_space(5.);
+        ui.horizontal(|ui| {
+            if !self.read_only() && ui.button("Create it").clicked() {
+                self.current_text = String::new();
+                self.backup_text = String::new();
+                self.mode = AppMode::NewNote;
+            }
+            if ui.button("Cancel").clicked() {
+                self.current_note = None;
+            }
+        });
+    }
+
+    // Revision timeline for the current note
+    // pick one to look at , restore makes a new revision.
+    fn show_history(&mut self, ui: &mut Ui) {
+        let Some(current_note) = self.current_note.clone() else {
+            self.mode = AppMode::Idle;
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.strong(format!("History : {}", &current_note.id));
+            ui.add_space(20.);
+            if ui.button("Back").clicked() {
+                self.revision = None;
+                self.mode = AppMode::Idle;
+            }
+        });
+        ui.separator();
+        egui::ScrollArea::vertical()
+            .id_salt("revisions")
+            .max_height(120.)
+            .show(ui, |ui| {
+                ui.with_layout(egui::Layout::top_down_justified(egui::Align::LEFT), |ui| {
+                    for (pos, rev) in self.history.iter().enumerate() {
+                        let selected = match &self.revision {
+                            Some((hash, _)) => *hash == rev.hash,
+                            None => false,
+                        };
+                        let mut label = format!(
+                            "{} | {} bytes | {}",
+                            format_timestamp(rev.updated),
+                            rev.size,
+                            rev.hash.fmt_short()
+                        );
+                        if pos == 0 {
+                            label.push_str(" (current)");
+                        }
+                        if rev.is_delete {
+                            label.push_str(" (hidden)");
+                        }
+                        if ui.selectable_label(selected, label).clicked() {
+                            self.cmd(Command::GetRevision(current_note.id.clone(), rev.hash));
+                        }
+                    }
+                });
+            });
+        ui.separator();
+        if let Some((hash, note)) = self.revision.clone() {
+            // the one before is next down the list
+            let previous = self
+                .history
+                .iter()
+                .skip_while(|rev| rev.hash != hash)
+                .nth(1)
+                .map(|rev| rev.hash);
+            ui.horizontal(|ui| {
+                ui.label(format!("Revision {}", hash.fmt_short()));
+                ui.add_space(20.);
+                if !self.read_only() && ui.button("Restore").clicked() {
+                    self.cmd(Command::RestoreRevision(current_note.id.clone(), hash));
+                    self.revision = None;
+                    self.mode = AppMode::Idle;
+                }
+                if ui.button("Compare with current").clicked() {
+                    self.diff_back = AppMode::History;
+                    self.cmd(Command::DiffRevisions(current_note.id.clone(), hash, None));
+                }
+                if let Some(previous) = previous {
+                    if ui.button("Compare with previous").clicked() {
+                        self.diff_back = AppMode::History;
+                        self.cmd(Command::DiffRevisions(
+                            current_note.id.clone(),
+                            previous,
+                            Some(hash),
+                        ));
+                    }
+                }
+            });
+            ui.separator();
+            let viewer = CommonMarkViewer::new();
+            viewer.show_scrollable("revision", ui, &mut self.cache, note.text.as_str());
+        }
+    }
+
+    // Recovery , pick a snapshot , look inside , put notes back
+    fn show_snapshots(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.strong("Snapshots");
+            ui.add_space(20.);
+            if ui.button("Back").clicked() {
+                self.snapshot = None;
+                self.mode = AppMode::Idle;
+            }
+        });
+        ui.separator();
+        if self.snapshots.is_empty() {
+            ui.label("No snapshots yet , Export makes one.");
+            return;
+        }
+        egui::ScrollArea::vertical()
+            .id_salt("snapshots")
+            .max_height(120.)
+            .show(ui, |ui| {
+                ui.with_layout(egui::Layout::top_down_justified(egui::Align::LEFT), |ui| {
+                    for snapshot in self.snapshots.iter() {
+                        let selected = match &self.snapshot {
+                            Some((hash, _)) => *hash == snapshot.hash,
+                            None => false,
+                        };
+                        let label = format!(
+                            "{} | {} notes | {}",
+                            format_timestamp(snapshot.time),
+                            snapshot.count,
+                            snapshot.hash.fmt_short()
+                        );
+                        if ui.selectable_label(selected, label).clicked() {
+                            self.cmd(Command::GetSnapshot(snapshot.hash));
+                        }
+                    }
+                });
+            });
+        ui.separator();
+        let Some((hash, notes)) = self.snapshot.clone() else {
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label(format!("Snapshot {}", hash.fmt_short()));
+            ui.add_space(20.);
+            // a read only doc can look back but not restore
+            if !self.read_only() {
+                let picked = !self.snapshot_picks.is_empty();
+                if ui
+                    .add_enabled(picked, egui::Button::new("Restore Selected"))
+                    .clicked()
+                {
+                    let ids = self.snapshot_picks.iter().cloned().collect();
+                    self.cmd(Command::RestoreSnapshot(hash, Some(ids)));
+                    self.snapshot_picks.clear();
+                }
+                if ui.button("Restore All").clicked() {
+                    self.cmd(Command::RestoreSnapshot(hash, None));
+                    self.snapshot_picks.clear();
+                }
+                ui.add_space(20.);
+            }
+            // newest first , so the older one is next down
+            let previous = self
+                .snapshots
+                .iter()
+                .skip_while(|s| s.hash != hash)
+                .nth(1)
+                .map(|s| s.tag.clone());
+            let tag = self
+                .snapshots
+                .iter()
+                .find(|s| s.hash == hash)
+                .map(|s| s.tag.clone())
+                .unwrap_or(hash.to_string());
+            if ui.button("Compare with now").clicked() {
+                self.diff_back = AppMode::Snapshots;
+                self.cmd(Command::DiffSnapshots(tag.clone(), None));
+            }
+            if let Some(previous) = previous {
+                if ui.button("Compare with previous").clicked() {
+                    self.diff_back = AppMode::Snapshots;
+                    self.cmd(Command::DiffSnapshots(previous, Some(tag)));
+                }
+            }
+        });
+        ui.separator();
+        ui.columns(2, |columns| {
+            egui::ScrollArea::vertical()
+                .id_salt("snapshot notes")
+                .show(&mut columns[0], |ui| {
+                    for note in notes.iter() {
+                        ui.horizontal(|ui| {
+                            let mut picked = self.snapshot_picks.contains(&note.id);
+                            if ui.checkbox(&mut picked, "").changed() {
+                                if picked {
+                                    self.snapshot_picks.insert(note.id.clone());
+                                } else {
+                                    self.snapshot_picks.remove(&note.id);
+                                }
+                            }
+                            let selected = match &self.snapshot_preview {
+                                Some(preview) => preview.id == note.id,
+                                None => false,
+                            };
+                            let mut label = RichText::new(&note.id);
+                            if note.is_delete {
+                                label = label.italics().weak();
+                            }
+                            if ui.selectable_label(selected, label).clicked() {
+                                self.snapshot_preview = Some(note.clone());
+                            }
+                        });
+                    }
+                });
+            if let Some(note) = &self.snapshot_preview {
+                let ui = &mut columns[1];
+                if note.updated != 0 {
+                    ui.small(format!("updated {}", format_timestamp(note.updated)));
+                }
+                if note.is_delete {
+                    ui.small("hidden , Restore All leaves it out");
+                }
+                let viewer = CommonMarkViewer::new();
+                viewer.show_scrollable("snapshot preview", ui, &mut self.cache, note.text.as_str());
+            }
+        });
+    }
+
+    // What changed , added and removed notes and the lines inside the modified ones
+    fn show_diff(&mut self, ui: &mut Ui) {
+        let Some((title, changes)) = &self.diff else {
+            self.mode = self.diff_back;
+            return;
+        };
+        let mut back = false;
+        ui.horizontal(|ui| {
+            ui.strong(format!("Diff : {}", title));
+            ui.add_space(20.);
+            if ui.button("Back").clicked() {
+                back = true;
+            }
+        });
+        ui.separator();
+        if changes.is_empty() {
+            ui.label("No changes.");
+        }
+        let added = egui::Color32::LIGHT_GREEN;
+        let removed = egui::Color32::LIGHT_RED;
+        egui::ScrollArea::vertical().id_salt("diff").show(ui, |ui| {
+            for change in changes.iter() {
+                match change {
+                    NoteChange::Added(id) => {
+                        ui.label(RichText::new(format!("+ {id}")).color(added));
+                    }
+                    NoteChange::Removed(id) => {
+                        ui.label(RichText::new(format!("- {id}")).color(removed));
+                    }
+                    NoteChange::Modified(id, lines) => {
+                        let header = RichText::new(format!("~ {id}")).color(egui::Color32::YELLOW);
+                        egui::CollapsingHeader::new(header)
+                            .id_salt(id)
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                for line in with_context(lines, CONTEXT) {
+                                    let text = match line {
+                                        Some(DiffLine::Same(l)) => RichText::new(format!("  {l}")),
+                                        Some(DiffLine::Added(l)) => {
+                                            RichText::new(format!("+ {l}")).color(added)
+                                        }
+                                        Some(DiffLine::Removed(l)) => {
+                                            RichText::new(format!("- {l}")).color(removed)
+                                        }
+                                        None => RichText::new("  ...").weak(),
+                                    };
+                                    ui.label(text.monospace());
+                                }
+                            });
+                    }
+                }
+            }
+        });
+        if back {
+            self.diff = None;
+            self.mode = self.diff_back;
+        }
+    }
+
+    // Side by side versions from each author
+    // pick one (or edit) and save it as the merged version.
+    fn show_conflict(&mut self, ui: &mut Ui) {
+        let Some((id, versions)) = self.versions.clone() else {
+            self.mode = AppMode::Idle;
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.strong(format!("Conflict : {}", &id));
+            ui.add_space(20.);
+            if !self.read_only() && ui.button("Save Merge").clicked() {
+                let heads = versions.iter().map(|v| v.hash).collect();
+                self.cmd(Command::ResolveConflict(
+                    id.clone(),
+                    self.merge_text.clone(),
+                    heads,
+                ));
+                self.versions = None;
+                self.mode = AppMode::Idle;
+            }
+            if ui.button("Cancel").clicked() {
+                self.mode = AppMode::Idle;
+            }
+        });
+        ui.separator();
+        ui.columns(versions.len(), |columns| {
+            for (pos, version) in versions.iter().enumerate() {
+                let ui = &mut columns[pos];
+                ui.label(format!(
+                    "{} @ {}",
+                    version.author.fmt_short(),
+                    format_timestamp(version.note.updated)
+                ));
+                if ui.button("Use this").clicked() {
+                    self.merge_text = version.note.text.clone();
+                }
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .id_salt(("version", pos))
+                    .max_height(200.)
+                    .show(ui, |ui| {
+                        ui.label(RichText::new(&version.note.text).monospace());
+                    });
+            }
+        });
+        ui.separator();
+        ui.small("Merged text");
+        egui::ScrollArea::vertical()
+            .id_salt("merge")
+            .show(ui, |ui| {
+                let _merge = egui::TextEdit::multiline(&mut self.merge_text)
+                    .desired_width(f32::INFINITY)
+                    .show(ui);
+            });
+    }
+
+    // Move or rename a folder , everything under it gets re-keyed
+    fn show_move_folder(&mut self, ui: &mut Ui) {
+        let Some((from, to)) = &mut self.folder_move else {
+            self.mode = AppMode::Idle;
+            return;
+        };
+        ui.strong(format!("Move folder : {}", from));
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("New path");
+            ui.add(egui::TextEdit::singleline(to).desired_width(250.));
+        });
+        ui.add_space(5.);
+        let mut done = false;
+        ui.horizontal(|ui| {
+            if ui.button("Move").clicked() {
+                let to = normalize_path(to);
+                if !to.is_empty() {
+                    self.worker
+                        .command_tx
+                        .send_blocking(Command::MoveFolder(from.clone(), to))
+                        .expect("Worker is not responding");
+                    done = true;
+                }
+            }
+            if ui.button("Cancel").clicked() {
+                done = true;
+            }
+        });
+        if done {
+            self.folder_move = None;
+            self.mode = AppMode::Idle;
+        }
+    }
+
+    // Import a folder of markdown , ask before clobbering anything
+    fn show_import(&mut self, ui: &mut Ui) {
+        let Some(path) = self.import_path.clone() else {
+            self.mode = AppMode::Idle;
+            return;
+        };
+        ui.strong("Import markdown");
+        ui.separator();
+        ui.label(format!("From {}", path.display()));
+        ui.small("Note names come from the file paths inside the folder.");
+        ui.add_space(5.);
+        ui.checkbox(&mut self.import_overwrite, "Overwrite notes that already exist");
+        ui.add_space(5.);
+        ui.horizontal(|ui| {
+            if ui.button("Import").clicked() {
+                self.cmd(Command::Import(path, self.import_overwrite));
+                self.import_path = None;
+                self.mode = AppMode::Idle;
+            }
+            if ui.button("Cancel").clicked() {
+                self.import_path = None;
+                self.mode = AppMode::Idle;
+            }
+        });
+    }
+
+    // Hidden notes , put them back or get rid of them
+    fn show_trash(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.strong("Trash");
+            ui.add_space(20.);
+            if ui.button("Back").clicked() {
+                self.mode = AppMode::Idle;
+            }
+        });
+        if self.config.trash_days > 0 {
+            ui.small(format!(
+                "Notes are deleted for good after {} days in the trash.",
+                self.config.trash_days
+            ));
+        }
+        ui.small(
+            "Deleting removes your copy. A note other people have edited \
+             stays hidden here until they delete their copies too.",
+        );
+        ui.separator();
+        if self.trash.is_empty() {
+            ui.label("The trash is empty.");
+            return;
+        }
+        let writable = !self.read_only();
+        egui::ScrollArea::vertical().id_salt("trash").show(ui, |ui| {
+            egui::Grid::new("trash grid").striped(true).show(ui, |ui| {
+                for trashed in self.trash.clone() {
+                    ui.label(&trashed.id);
+                    ui.small(format!(
+                        "hidden {} by {}",
+                        format_timestamp(trashed.hidden_at),
+                        trashed.hidden_by
+                    ));
+                    if writable && ui.button("Restore").clicked() {
+                        self.cmd(Command::RestoreNote(trashed.id.clone()));
+                    }
+                    let forever = RichText::new("Delete Forever").color(egui::Color32::LIGHT_RED);
+                    if writable && ui.button(forever).clicked() {
+                        self.cmd(Command::DeleteForever(trashed.id.clone()));
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    // Joined with a read ticket
+    fn read_only(&self) -> bool {
+        match &self.config.doc_key {
+            Some(key) => self.config.doc_set(key).is_some_and(|d| d.read_only),
+            None => false,
+        }
+    }
+
+    // Pick which doc set is on show
+    fn doc_switcher(&mut self, ui: &mut Ui) {
+        if self.config.doc_sets.is_empty() {
+            return;
+        }
+        let current = self.config.doc_key.clone().unwrap_or_default();
+        let shown = match self.config.doc_set(&current) {
+            Some(doc_set) => doc_set.name.clone(),
+            None => String::from("..."),
+        };
+        let mut picked = current.clone();
+        egui::ComboBox::from_id_salt("doc sets")
+            .selected_text(shown)
+            .show_ui(ui, |ui| {
+                for doc_set in self.config.doc_sets.iter() {
+                    ui.selectable_value(&mut picked, doc_set.doc_key.clone(), &doc_set.name);
+                }
+            });
+        if picked != current {
+            self.cmd(Command::SwitchDoc(picked));
+            self.current_note = None;
+            self.backlinks = None;
+            self.mode = AppMode::Idle;
+        }
+        ui.add_space(10.);
+    }
+
+    // Name a new doc set
+    fn show_new_doc(&mut self, ui: &mut Ui) {
+        ui.strong("New Doc Set");
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.add(egui::TextEdit::singleline(&mut self.doc_name).desired_width(250.));
+        });
+        ui.add_space(5.);
+        ui.horizontal(|ui| {
+            if ui.button("Create").clicked() {
+                self.new_doc();
+            }
+            if ui.button("Cancel").clicked() {
+                self.mode = AppMode::Idle;
+            }
+        });
+    }
+
+    // Make the doc and wait for its share ticket
+    fn new_doc(&mut self) {
+        self.cmd(Command::NewDoc(self.doc_name.clone()));
+        self.current_note = None;
+        self.backlinks = None;
+        self.share_ticket = None;
+        // the worker hands back a write ticket for teammates
+        self.share_write = true;
+        self.mode = AppMode::ShareTicket;
+    }
+
+    // Leaving deletes our copy , make sure
+    fn show_leave_doc(&mut self, ui: &mut Ui) {
+        let Some(key) = self.config.doc_key.clone() else {
+            self.mode = AppMode::Idle;
+            return;
+        };
+        let name = match self.config.doc_set(&key) {
+            Some(doc_set) => doc_set.name.clone(),
+            None => key.clone(),
+        };
+        ui.strong(format!("Leave {name}"));
+        ui.separator();
+        ui.label("Stops syncing this doc set and deletes the copy on this machine.");
+        ui.small("Other peers keep theirs , join again with a share ticket.");
+        ui.add_space(5.);
+        ui.horizontal(|ui| {
+            let text = RichText::new("Leave").color(egui::Color32::LIGHT_RED);
+            if ui.button(text).clicked() {
+                self.cmd(Command::LeaveDoc(key.clone()));
+                self.current_note = None;
+                self.backlinks = None;
+                // the last one , back to the start
+                if self.config.doc_sets.iter().all(|d| d.doc_key == key) {
+                    self.receiver_ticket.clear();
+                    self.mode = AppMode::GetDocTicket;
+                } else {
+                    self.mode = AppMode::Idle;
+                }
+            }
+            if ui.button("Cancel").clicked() {
+                self.mode = AppMode::Idle;
+            }
+        });
+    }
+
+    // Make sure before deleting every hidden note
+    fn show_confirm_delete(&mut self, ui: &mut Ui) {
+        ui.strong("Delete Hidden");
+        ui.separator();
+        if self.trash.is_empty() {
+            ui.label("Nothing is hidden.");
+        } else {
+            ui.label(format!(
+                "Delete {} hidden notes for good ? Only snapshots will still have them.",
+                self.trash.len()
+            ));
+        }
+        ui.add_space(5.);
+        ui.horizontal(|ui| {
+            let text = RichText::new("Delete").color(egui::Color32::LIGHT_RED);
+            if ui
+                .add_enabled(!self.trash.is_empty(), egui::Button::new(text))
+                .clicked()
+            {
+                self.cmd(Command::DeleteHidden);
+                self.mode = AppMode::Idle;
+            }
+            if ui.button("Cancel").clicked() {
+                self.mode = AppMode::Idle;
+            }
+        });
+    }
+
+    // Rename the current note , keeps the history and created time
+    fn show_rename(&mut self, ui: &mut Ui) {
+        let Some(current_note) = self.current_note.clone() else {
+            self.mode = AppMode::Idle;
+            return;
+        };
+        ui.strong(format!("Rename : {}", &current_note.id));
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("New name");
+            ui.add(egui::TextEdit::singleline(&mut self.rename_to).desired_width(250.));
+        });
+        ui.add_space(5.);
+        ui.horizontal(|ui| {
+            if ui.button("Rename").clicked() {
+                let to = normalize_path(&self.rename_to);
+                if !to.is_empty() && to != current_note.id {
+                    self.cmd(Command::RenameNote(current_note.id.clone(), to));
+                }
+                self.mode = AppMode::Idle;
+            }
+            if ui.button("Cancel").clicked() {
+                self.mode = AppMode::Idle;
+            }
+        });
+    }
+
+    // Ranked search results with the hits highlighted
+    fn show_search(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.strong(format!("Search : {}", &self.search_query));
+            ui.add_space(20.);
+            if ui.button("Close").clicked() {
+                self.mode = AppMode::Idle;
+            }
+        });
+        ui.separator();
+        if self.search_results.is_empty() {
+            ui.label("No results");
+            return;
+        }
+        let mut open = None;
+        egui::ScrollArea::vertical()
+            .id_salt("search results")
+            .show(ui, |ui| {
+                for hit in self.search_results.iter() {
+                    if ui.link(RichText::new(&hit.id).strong()).clicked() {
+                        open = Some(hit.id.clone());
+                    }
+                    ui.horizontal_wrapped(|ui| {
+                        ui.spacing_mut().item_spacing.x = 0.;
+                        for (text, matched) in hit.snippet.iter() {
+                            if *matched {
+                                ui.label(
+                                    RichText::new(text)
+                                        .strong()
+                                        .background_color(egui::Color32::from_rgb(90, 80, 0)),
+                                );
+                            } else {
+                                ui.label(text);
+                            }
+                        }
+                    });
+                    ui.add_space(6.);
+                }
+            });
+        if let Some(id) = open {
+            self.cmd(Command::GetNote(id));
+            self.mode = AppMode::Idle;
+        }
+    }
+
     // Show the config editor ,  needs a restart to work
     fn show_config(&mut self,ctx: &egui::Context, ui: &mut Ui) {
         // config editor
@@ -503,6 +1654,38 @@ impl AppState {
                 }
             }
         });
+        ui.add_space(5.);
+        ui.small("Snapshot every (minutes , 0 is off)");
+        ui.add(egui::DragValue::new(&mut self.config.snapshot_minutes).range(0..=24 * 60));
+        ui.add_space(5.);
+        ui.small("Empty the trash after (days , 0 is never)");
+        ui.add(egui::DragValue::new(&mut self.config.trash_days).range(0..=3650));
+        ui.add_space(5.);
+        ui.checkbox(&mut self.config.mirror, "Mirror notes to a folder")
+            .on_hover_text("keeps <download path>/mirror in step , edits there come back in");
+        ui.add_space(5.);
+        ui.checkbox(&mut self.config.api, "Local http api")
+            .on_hover_text("notes over http on 127.0.0.1 , for scripts and editor plugins");
+        if self.config.api {
+            ui.horizontal(|ui| {
+                ui.small("Port");
+                ui.add(egui::DragValue::new(&mut self.config.api_port).range(1024..=65535));
+            });
+            ui.small("Token");
+            ui.horizontal(|ui| {
+                if self.config.api_token.is_empty() {
+                    ui.label("made when saved");
+                } else {
+                    ui.monospace(&self.config.api_token);
+                    if ui.button("Copy").clicked() {
+                        ctx.copy_text(self.config.api_token.clone());
+                    }
+                }
+                if ui.button("New Token").clicked() {
+                    self.config.api_token = crate::api::new_token();
+                }
+            });
+        }
         ui.separator();
 
         if ui.button("Save Config").clicked() {
@@ -541,6 +1724,11 @@ impl AppState {
 
     // Show the new document ticket fetch box
     fn ticket_box(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.add(egui::TextEdit::singleline(&mut self.doc_name).desired_width(250.));
+        });
+        ui.add_space(5.);
         ui.label("Docs share ticket");
         ui.add_space(8.);
         let _ticket_edit = egui::TextEdit::multiline(&mut self.receiver_ticket)
@@ -550,16 +1738,17 @@ impl AppState {
         ui.horizontal(|ui| {
             if ui.button("Create Duplicate").clicked() {
                 // Fetch to the default path
-                self.cmd(Command::DocTicket(self.receiver_ticket.clone()));
-                self.cmd(Command::GetNotes);
+                let name = self.doc_name.clone();
+                self.cmd(Command::JoinDoc(name, self.receiver_ticket.clone()));
+                self.current_note = None;
                 self.mode = AppMode::Idle;
             };
             if ui.button("New Doc Set").clicked() {
-                //TODO make a new doc
-                self.messages.push(MessageDisplay {
-                    text: "(TODO) Make new doc".to_string(),
-                    mtype: MessageType::Error,
-                })
+                self.new_doc();
+            }
+            // joining another one , not the first run
+            if self.config.doc_key.is_some() && ui.button("Cancel").clicked() {
+                self.mode = AppMode::Idle;
             }
         });
     }
@@ -605,6 +1794,28 @@ impl AppState {
     }
 }
 
+// Unix seconds into local time for display
+fn format_timestamp(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(dt) => dt
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+        None => String::from("unknown"),
+    }
+}
+
+// Rough age of a unix time for the status bar
+fn format_ago(timestamp: i64) -> String {
+    let secs = (Utc::now().timestamp() - timestamp).max(0);
+    match secs {
+        0..60 => String::from("just now"),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86400 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
 fn format_seconds_as_hms(total_seconds: u64) -> String {
     let hours = total_seconds / 3600;
     let minutes = (total_seconds % 3600) / 60;
@@ -618,12 +1829,18 @@ fn format_seconds_as_hms(total_seconds: u64) -> String {
 pub struct NotesUi {
     // docs is fast enough not to have to store in the egui side.
     notes: BTreeMap<String, bool>,
+    // notes where the authors disagree
+    conflicts: BTreeSet<String>,
+    // folder picked from the context menu
+    move_request: Option<String>,
 }
 
 impl NotesUi {
     pub fn new() -> Self {
         Self {
             notes: BTreeMap::new(),
+            conflicts: BTreeSet::new(),
+            move_request: None,
         }
     }
 }
@@ -641,38 +1858,106 @@ impl NotesUi {
         self.notes.insert(note.id.clone(), true);
     }
 
+    fn set_conflicts(&mut self, conflicts: Vec<String>) {
+        self.conflicts = conflicts.into_iter().collect();
+    }
+
     fn clear_selection(&mut self) {
         for (_, active) in self.notes.iter_mut() {
             *active = false;
         }
     }
 
+    // a folder asked to be moved (from the context menu)
+    fn take_move_request(&mut self) -> Option<String> {
+        self.move_request.take()
+    }
+
     // hand back the selected item
     // returns the name of the selcted item as an option
     // load the note if Some.
     fn show(&mut self, ui: &mut Ui) -> Option<String> {
         ui.add_space(10.);
+        let tree = FolderTree::build(self.notes.keys());
         let mut val = None;
         egui::ScrollArea::vertical().show(ui, |ui| {
             ui.with_layout(egui::Layout::top_down_justified(egui::Align::LEFT), |ui| {
-                let mut active_pos = usize::MAX;
-
-                for (pos, (name, active)) in self.notes.iter_mut().enumerate() {
-                    if ui.toggle_value(active, name).clicked() {
-                        active_pos = pos;
-                        val = Some(name.clone());
-                    }
-                }
-                // Make sure only one is active
-                if active_pos != usize::MAX {
-                    for (pos, (_name, active)) in self.notes.iter_mut().enumerate() {
-                        if active_pos != pos {
-                            *active = false;
-                        }
-                    }
-                }
+                self.show_folder(ui, &tree, "", &mut val);
             });
         });
+        // Make sure only one is active
+        if let Some(selected) = &val {
+            for (name, active) in self.notes.iter_mut() {
+                *active = name == selected;
+            }
+        }
         return val;
     }
+
+    // Folders first (collapsible) then the notes in this folder
+    fn show_folder(
+        &mut self,
+        ui: &mut Ui,
+        tree: &FolderTree,
+        path: &str,
+        val: &mut Option<String>,
+    ) {
+        for (name, sub) in tree.folders.iter() {
+            let full = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{path}/{name}")
+            };
+            let response = egui::CollapsingHeader::new(name)
+                .id_salt(&full)
+                .show(ui, |ui| {
+                    self.show_folder(ui, sub, &full, val);
+                });
+            response.header_response.context_menu(|ui| {
+                if ui.button("Move / Rename").clicked() {
+                    self.move_request = Some(full.clone());
+                    ui.close();
+                }
+            });
+        }
+        for id in tree.notes.iter() {
+            let leaf = id.rsplit('/').next().unwrap_or(id);
+            let active = self.notes.get(id).copied().unwrap_or(false);
+            // mark the conflicted ones
+            let label = if self.conflicts.contains(id) {
+                RichText::new(format!("⚠ {leaf}")).color(egui::Color32::LIGHT_RED)
+            } else {
+                RichText::new(leaf)
+            };
+            if ui.selectable_label(active, label).clicked() {
+                *val = Some(id.clone());
+            }
+        }
+    }
+}
+
+// Folder tree built from the note ids
+// ids are paths , markers for empty folders end in a slash
+#[derive(Default)]
+struct FolderTree {
+    folders: BTreeMap<String, FolderTree>,
+    notes: Vec<String>,
+}
+
+impl FolderTree {
+    fn build<'a>(ids: impl Iterator<Item = &'a String>) -> Self {
+        let mut root = Self::default();
+        for id in ids {
+            let mut parts: Vec<&str> = id.split('/').collect();
+            let leaf = parts.pop().unwrap_or_default();
+            let mut node = &mut root;
+            for part in parts.into_iter().filter(|p| !p.is_empty()) {
+                node = node.folders.entry(part.to_string()).or_default();
+            }
+            if !leaf.is_empty() {
+                node.notes.push(id.clone());
+            }
+        }
+        root
+    }
 }
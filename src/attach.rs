@@ -0,0 +1,71 @@
+// File attachments on notes
+// Files go into the blob store and the note gets a markdown reference
+//   images      ![name](<blob:HASH/name>)
+//   everything  [name](<blob:HASH/name>)
+// Peers fetch the blob when the note is looked at.
+
+use std::path::Path;
+
+use iroh_blobs::Hash;
+
+// Url scheme for attachments inside the viewer
+pub const BLOB_SCHEME: &str = "blob:";
+
+// Things the image loaders can show inline
+const IMAGE_TYPES: [&str; 6] = ["png", "jpg", "jpeg", "gif", "bmp", "webp"];
+
+pub fn is_image(name: &str) -> bool {
+    match Path::new(name).extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy().to_lowercase();
+            IMAGE_TYPES.contains(&ext.as_str())
+        }
+        None => false,
+    }
+}
+
+// The markdown to drop into the note
+pub fn attachment_markdown(name: &str, hash: &Hash) -> String {
+    // keep the markdown in one piece
+    let name = name.replace(['<', '>', '[', ']'], "_");
+    let bang = if is_image(&name) { "!" } else { "" };
+    format!("{bang}[{name}](<{BLOB_SCHEME}{hash}/{name}>)")
+}
+
+// Split a blob url into the hash and file name
+pub fn parse_blob_url(url: &str) -> Option<(Hash, String)> {
+    let rest = url.strip_prefix(BLOB_SCHEME)?;
+    let (hash, name) = rest.split_once('/')?;
+    let hash = hash.parse().ok()?;
+    Some((hash, name.to_string()))
+}
+
+// Every image attachment in a note , so the gui can ask for the bytes
+pub fn image_urls(text: &str) -> Vec<String> {
+    blob_urls(text)
+        .into_iter()
+        .filter(|url| match parse_blob_url(url) {
+            Some((_, name)) => is_image(&name),
+            None => false,
+        })
+        .collect()
+}
+
+// Every attachment in a note
+pub fn blob_urls(text: &str) -> Vec<String> {
+    let mut urls = Vec::new();
+    let marker = format!("](<{BLOB_SCHEME}");
+    let mut pos = 0;
+    while let Some(found) = text[pos..].find(&marker) {
+        let start = pos + found + 3;
+        let Some(end) = text[start..].find('>') else {
+            break;
+        };
+        let url = &text[start..start + end];
+        if parse_blob_url(url).is_some() {
+            urls.push(url.to_string());
+        }
+        pos = start + end;
+    }
+    urls
+}
@@ -0,0 +1,360 @@
+// Command line interface
+// runs the worker without a window , results go to stdout for scripts.
+//   liminal-doc list
+//   liminal-doc cat <id>
+//   liminal-doc put <id> < file
+//   liminal-doc hide <id>
+//   liminal-doc export <dir>
+//   liminal-doc import [--overwrite] <dir>
+//   liminal-doc share [--read]
+//   liminal-doc join <ticket> [name]
+//   liminal-doc diff <old snapshot> [new snapshot]
+//   liminal-doc diff-note <id> <old revision> [new revision]
+//   liminal-doc daemon
+// --json anywhere gives json on stdout , progress goes to the log on stderr.
+// Snapshots are tag names (notes-...) or collection hashes,
+// leaving off the new one compares against the notes as they are now.
+// The store can only be open once , close the gui first.
+
+use std::io::Read;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use iroh_docs::DocTicket;
+use serde_json::json;
+use tracing::info;
+
+use crate::app::APP_NAME;
+use crate::comms::{Command, Config, Event, MessageType};
+use crate::daemon;
+use crate::diff::{CONTEXT, DiffLine, NoteChange, unified, with_context};
+use crate::notes::Note;
+use crate::worker::{Worker, WorkerHandle};
+
+const USAGE: &str = "usage:
+  liminal-doc list
+  liminal-doc cat <id>
+  liminal-doc put <id> < file
+  liminal-doc hide <id>
+  liminal-doc export <dir>
+  liminal-doc import [--overwrite] <dir>
+  liminal-doc share [--read]
+  liminal-doc join <ticket> [name]
+  liminal-doc diff <old snapshot> [new snapshot]
+  liminal-doc diff-note <id> <old revision> [new revision]
+  liminal-doc daemon
+add --json for json output";
+
+// The first words the cli answers to , anything else opens the gui
+// ( a file dropped on the icon , -psn_ from the macos finder ).
+const COMMANDS: &[&str] = &[
+    "daemon", "list", "cat", "put", "hide", "export", "import", "share", "join", "diff",
+    "diff-note", "help", "--help",
+];
+
+pub fn is_cli(args: &[String]) -> bool {
+    args.iter()
+        .find(|a| *a != "--json")
+        .is_some_and(|a| COMMANDS.contains(&a.as_str()))
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+    let json = args.iter().any(|a| a == "--json");
+    let args: Vec<&str> = args
+        .iter()
+        .map(|a| a.as_str())
+        .filter(|a| *a != "--json")
+        .collect();
+    match args.as_slice() {
+        ["daemon"] => daemon::run(),
+        ["list"] => list(json),
+        ["cat", id] => cat(id, json),
+        ["put", id] => put(id, json),
+        ["hide", id] => hide(id, json),
+        ["export", dir] => export(PathBuf::from(dir), json),
+        ["import", dir] => import(PathBuf::from(dir), false, json),
+        ["import", "--overwrite", dir] => import(PathBuf::from(dir), true, json),
+        ["share"] => share(true, json),
+        ["share", "--read"] => share(false, json),
+        ["join", ticket] => join(ticket, "", json),
+        ["join", ticket, name] => join(ticket, name, json),
+        ["diff", old] => diff(Command::DiffSnapshots(old.to_string(), None), json),
+        ["diff", old, new] => diff(
+            Command::DiffSnapshots(old.to_string(), Some(new.to_string())),
+            json,
+        ),
+        ["diff-note", id, old] => diff(
+            Command::DiffRevisions(id.to_string(), old.parse()?, None),
+            json,
+        ),
+        ["diff-note", id, old, new] => diff(
+            Command::DiffRevisions(id.to_string(), old.parse()?, Some(new.parse()?)),
+            json,
+        ),
+        ["help"] | ["--help"] => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => bail!(USAGE),
+    }
+}
+
+// Note ids , one a line
+fn list(json: bool) -> Result<()> {
+    let handle = open()?;
+    send(&handle, Command::GetNotes)?;
+    let ids = wait_for(&handle, |event| match event {
+        Event::NoteList(ids) => Some(ids),
+        _ => None,
+    })?;
+    close(&handle)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&ids)?);
+    } else {
+        for id in ids {
+            println!("{id}");
+        }
+    }
+    Ok(())
+}
+
+// The text of a note
+fn cat(id: &str, json: bool) -> Result<()> {
+    let handle = open()?;
+    let note = get_note(&handle, id)?;
+    close(&handle)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&note)?);
+    } else {
+        print!("{}", note.text);
+    }
+    Ok(())
+}
+
+// Replace ( or make ) a note from stdin
+fn put(id: &str, json: bool) -> Result<()> {
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text)?;
+    let handle = open()?;
+    send(&handle, Command::SaveNote(id.to_string(), text))?;
+    // in order , so this comes back after the save
+    let note = get_note(&handle, id)?;
+    close(&handle)?;
+    if json {
+        println!("{}", json!({ "id": note.id, "updated": note.updated }));
+    } else {
+        info!("saved {}", note.id);
+    }
+    Ok(())
+}
+
+// Hide a note , already hidden is fine
+fn hide(id: &str, json: bool) -> Result<()> {
+    let handle = open()?;
+    let note = get_note(&handle, id)?;
+    send(&handle, Command::SetHidden(note.id.clone(), true))?;
+    ping(&handle)?;
+    close(&handle)?;
+    if json {
+        println!("{}", json!({ "id": note.id, "hidden": true }));
+    } else {
+        info!("hid {}", note.id);
+    }
+    Ok(())
+}
+
+// Every note as markdown into a folder
+fn export(dir: PathBuf, json: bool) -> Result<()> {
+    let handle = open()?;
+    send(&handle, Command::Export(Some(dir.clone())))?;
+    let messages = ping(&handle)?;
+    close(&handle)?;
+    if json {
+        println!("{}", json!({ "dir": dir, "messages": messages }));
+    }
+    Ok(())
+}
+
+// A folder of markdown in
+fn import(dir: PathBuf, overwrite: bool, json: bool) -> Result<()> {
+    let handle = open()?;
+    send(&handle, Command::Import(dir.clone(), overwrite))?;
+    let messages = ping(&handle)?;
+    close(&handle)?;
+    if json {
+        println!("{}", json!({ "dir": dir, "messages": messages }));
+    }
+    Ok(())
+}
+
+// A share ticket for the current doc set
+fn share(write: bool, json: bool) -> Result<()> {
+    let handle = open()?;
+    send(&handle, Command::GetShareTicket(write))?;
+    let ticket = wait_for(&handle, |event| match event {
+        Event::SendShareTicket(ticket) => Some(ticket),
+        _ => None,
+    })?;
+    close(&handle)?;
+    if json {
+        println!("{}", json!({ "ticket": ticket, "write": write }));
+    } else {
+        println!("{ticket}");
+    }
+    Ok(())
+}
+
+// Join a doc set , it becomes the current one
+// works without a doc set up yet.
+fn join(ticket: &str, name: &str, json: bool) -> Result<()> {
+    let doc_ticket = DocTicket::from_str(ticket.trim())?;
+    let key = doc_ticket.capability.id().to_string();
+    let config: Config = confy::load(APP_NAME, None)?;
+    let handle = Worker::spawn(config);
+    wait_for(&handle, |event| matches!(event, Event::SetReady).then_some(()))?;
+    send(&handle, Command::JoinDoc(name.to_string(), ticket.to_string()))?;
+    ping(&handle)?;
+    close(&handle)?;
+    let config: Config = confy::load(APP_NAME, None)?;
+    let name = match config.doc_set(&key) {
+        Some(doc_set) => doc_set.name.clone(),
+        None => bail!("join did not stick"),
+    };
+    if json {
+        println!("{}", json!({ "doc_key": key, "name": name }));
+    } else {
+        println!("{name} {key}");
+    }
+    Ok(())
+}
+
+// Snapshot or revision differences
+fn diff(command: Command, json: bool) -> Result<()> {
+    let handle = open()?;
+    send(&handle, command)?;
+    let changes = wait_for(&handle, |event| match event {
+        Event::Diff(_, changes) => Some(changes),
+        _ => None,
+    })?;
+    close(&handle)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changes_json(&changes))?);
+    } else {
+        print!("{}", unified(&changes));
+    }
+    Ok(())
+}
+
+// Same prefixes as the unified text , null for a gap
+fn changes_json(changes: &[NoteChange]) -> serde_json::Value {
+    let changes: Vec<serde_json::Value> = changes
+        .iter()
+        .map(|change| match change {
+            NoteChange::Added(id) => json!({ "id": id, "change": "added" }),
+            NoteChange::Removed(id) => json!({ "id": id, "change": "removed" }),
+            NoteChange::Modified(id, lines) => {
+                let lines: Vec<Option<String>> = with_context(lines, CONTEXT)
+                    .into_iter()
+                    .map(|line| match line {
+                        Some(DiffLine::Same(l)) => Some(format!(" {l}")),
+                        Some(DiffLine::Added(l)) => Some(format!("+{l}")),
+                        Some(DiffLine::Removed(l)) => Some(format!("-{l}")),
+                        None => None,
+                    })
+                    .collect();
+                json!({ "id": id, "change": "modified", "lines": lines })
+            }
+        })
+        .collect();
+    serde_json::Value::Array(changes)
+}
+
+// Start the worker and open the doc from the saved config
+fn open() -> Result<WorkerHandle> {
+    let config: Config = confy::load(APP_NAME, None)?;
+    let Some(doc_id) = config.doc_key.clone() else {
+        bail!("no doc set up yet , join one or run the gui first");
+    };
+    let handle = Worker::spawn(config);
+    wait_for(&handle, |event| matches!(event, Event::SetReady).then_some(()))?;
+    send(&handle, Command::DocId(doc_id))?;
+    Ok(handle)
+}
+
+// Let the worker close the stores before we exit
+fn close(handle: &WorkerHandle) -> Result<()> {
+    send(handle, Command::Shutdown)?;
+    wait_for(handle, |event| matches!(event, Event::Stopped).then_some(()))
+}
+
+fn get_note(handle: &WorkerHandle, id: &str) -> Result<Note> {
+    send(handle, Command::GetNote(id.to_string()))?;
+    let note = wait_for(handle, |event| match event {
+        Event::SendNote(note) => Some(note),
+        _ => None,
+    })?;
+    if note.is_missing() {
+        bail!("no note called {id}");
+    }
+    Ok(note)
+}
+
+// Wait until everything sent so far is done , hands back what was said on the way
+fn ping(handle: &WorkerHandle) -> Result<Vec<String>> {
+    send(handle, Command::Ping)?;
+    let mut messages = Vec::new();
+    wait_for(handle, |event| match event {
+        Event::Message(message) => {
+            messages.push(message.text);
+            None
+        }
+        Event::Pong => Some(()),
+        _ => None,
+    })?;
+    Ok(messages)
+}
+
+fn send(handle: &WorkerHandle, command: Command) -> Result<()> {
+    handle
+        .command_tx
+        .send_blocking(command)
+        .map_err(|_| anyhow!("worker is not running"))
+}
+
+// Wait for the event we want , a red message from the worker ends the wait
+// other messages go to the log and config changes get saved like the gui does.
+fn wait_for<T>(handle: &WorkerHandle, mut pick: impl FnMut(Event) -> Option<T>) -> Result<T> {
+    loop {
+        let event = handle
+            .event_rx
+            .recv_blocking()
+            .map_err(|_| anyhow!("worker stopped"))?;
+        match &event {
+            Event::Message(message) => match message.mtype {
+                MessageType::Error => bail!("{}", message.text),
+                _ => info!("{}", message.text),
+            },
+            Event::SendConfig(config) => confy::store(APP_NAME, None, config)?,
+            _ => {}
+        }
+        if let Some(found) = pick(event) {
+            return Ok(found);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_commands_run_the_cli() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(is_cli(&args(&["list"])));
+        assert!(is_cli(&args(&["--json", "cat", "a"])));
+        assert!(!is_cli(&args(&[])));
+        assert!(!is_cli(&args(&["-psn_0_12345"])));
+        assert!(!is_cli(&args(&["/home/me/notes.md"])));
+    }
+}
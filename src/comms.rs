@@ -1,18 +1,24 @@
 // Comms between the gui and  the worker in it's own module.
 // Some of this lives on both sides ( be careful )
 
-use std::{path::PathBuf, sync::Arc};
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
 
 use anyhow::Result;
 use async_channel::Sender;
+use bytes::Bytes;
 use eframe::egui::{self};
 
 use egui::{Color32, Ui};
 use iroh::NodeAddr;
+use iroh_blobs::Hash;
 use serde_derive::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-use crate::notes::Note;
+use crate::api::{ApiCall, ApiReply};
+use crate::diff::NoteChange;
+use crate::notes::{Note, NoteVersion, Revision, Snapshot, Trashed};
+use crate::search::SearchHit;
+use crate::sync::SyncStatus;
 
 // Application Configuration
 // Application saved config
@@ -23,9 +29,64 @@ pub struct Config {
     pub download_path: PathBuf,
     pub store_path: PathBuf,
     pub secret_key: String,
+    // the doc set in use
     pub doc_key: Option<String>,
     pub author: Option<String>,
+    // from before doc sets , moved into doc_sets on start
     pub mothership: Option<Vec<NodeAddr>>,
+    #[serde(default)]
+    pub doc_sets: Vec<DocSet>,
+    // collection hash of the last export per doc key , unchanged notes are not written again
+    #[serde(default)]
+    pub last_exports: BTreeMap<String, String>,
+    // minutes between automatic snapshots , 0 is off
+    #[serde(default = "default_snapshot_minutes")]
+    pub snapshot_minutes: u64,
+    // keep a folder of .md files in step with the notes
+    #[serde(default)]
+    pub mirror: bool,
+    // days a hidden note stays in the trash , 0 keeps them
+    #[serde(default = "default_trash_days")]
+    pub trash_days: u64,
+    // local http api , loopback only
+    #[serde(default)]
+    pub api: bool,
+    #[serde(default = "default_api_port")]
+    pub api_port: u16,
+    // bearer token for the api , made when it is first switched on
+    #[serde(default)]
+    pub api_token: String,
+}
+
+// A named doc , each one syncs on its own
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DocSet {
+    pub name: String,
+    pub doc_key: String,
+    // peers to sync with , from the ticket it was joined with
+    #[serde(default)]
+    pub mothership: Option<Vec<NodeAddr>>,
+    // joined with a read ticket
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl Config {
+    pub fn doc_set(&self, key: &str) -> Option<&DocSet> {
+        self.doc_sets.iter().find(|d| d.doc_key == key)
+    }
+}
+
+fn default_snapshot_minutes() -> u64 {
+    60
+}
+
+fn default_trash_days() -> u64 {
+    30
+}
+
+fn default_api_port() -> u16 {
+    7733
 }
 
 // Update Callback
@@ -40,20 +101,45 @@ pub enum Event {
     SendConfig(Config),
     SendShareTicket(String),
     NoteList(Vec<String>),
+    Conflicts(Vec<String>),
+    NoteConflict(String, Vec<NoteVersion>),
     SendNote(Note),
+    NoteHistory(String, Vec<Revision>),
+    SendRevision(Hash, Note),
+    RemoteChange(String),
+    SearchResults(String, Vec<SearchHit>),
+    Backlinks(String, Vec<String>),
+    Attached(String),
+    Attachment(String, Bytes),
+    Snapshots(Vec<Snapshot>),
+    SnapshotNotes(Hash, Vec<Note>),
+    Trash(Vec<Trashed>),
+    Diff(String, Vec<NoteChange>),
     Tick(u64),
     StopTick,
     Finished,
     SetReady,
+    // doc key and what its sync is doing
+    SyncStatus(String, SyncStatus),
+    // the worker has shut down
+    Stopped,
+    // everything before the ping is done
+    Pong,
 }
 
 // Incoming commands from the egui interface
 // and the actor loop on  replication events.
 pub enum Command {
     Setup { callback: UpdateCallback },
-    DocTicket(String),
+    // name and ticket
+    JoinDoc(String, String),
+    // a brand new doc with this name
+    NewDoc(String),
     DocId(String),
-    GetShareTicket,
+    SwitchDoc(String),
+    LeaveDoc(String),
+    // true for a write ticket
+    GetShareTicket(bool),
     GetNotes,
     GetNote(String),
     SendConfig(Config),
@@ -62,7 +148,67 @@ pub enum Command {
     ResetTimer,
     DeleteHidden,
     HideNote(String),
-    Attach,
+    // a doc set's sync came back , catch up on what was missed
+    Reindex(String),
+    // hide ( true ) or show , no change if it is already that way
+    SetHidden(String, bool),
+    GetHistory(String),
+    GetRevision(String, Hash),
+    RestoreRevision(String, Hash),
+    ResolveConflict(String, String, Vec<Hash>),
+    ConvertNote(String),
+    NewFolder(String),
+    MoveFolder(String, String),
+    RenameNote(String, String),
+    Search(String),
+    AttachFile(PathBuf),
+    GetAttachment(String),
+    SaveAttachment(String),
+    // none is the download folder
+    Export(Option<PathBuf>),
+    GetSnapshots,
+    GetSnapshot(Hash),
+    RestoreSnapshot(Hash, Option<Vec<String>>),
+    SnapshotTick,
+    GetTrash,
+    RestoreNote(String),
+    DeleteForever(String),
+    DiffSnapshots(String, Option<String>),
+    DiffRevisions(String, Hash, Option<Hash>),
+    Import(PathBuf, bool),
+    MirrorScan,
+    Ping,
+    Shutdown,
+    // doc , note id
+    RemoteChange(String, String),
+    Attach(String),
+    // from the http api , the answer goes back on the channel
+    Api(ApiCall, ApiReply),
+}
+
+impl Command {
+    // Commands that change the doc , a read only doc turns these away
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::SaveNote(..)
+                | Command::NewNote(..)
+                | Command::DeleteHidden
+                | Command::HideNote(_)
+                | Command::SetHidden(..)
+                | Command::RestoreRevision(..)
+                | Command::ResolveConflict(..)
+                | Command::ConvertNote(_)
+                | Command::NewFolder(_)
+                | Command::MoveFolder(..)
+                | Command::RenameNote(..)
+                | Command::AttachFile(_)
+                | Command::RestoreSnapshot(..)
+                | Command::RestoreNote(_)
+                | Command::DeleteForever(_)
+                | Command::Import(..)
+        )
+    }
 }
 
 // Message types
@@ -185,12 +331,108 @@ impl MessageOut {
         Ok(())
     }
 
+    // Send the ids of conflicted notes up to the gui
+    pub async fn send_conflicts(&self, conflicts: Vec<String>) -> Result<()> {
+        self.emit(Event::Conflicts(conflicts)).await?;
+        Ok(())
+    }
+
+    // Send every author's version of a conflicted note
+    pub async fn send_versions(&self, id: String, versions: Vec<NoteVersion>) -> Result<()> {
+        self.emit(Event::NoteConflict(id, versions)).await?;
+        Ok(())
+    }
+
     // Send note up to the gui
     pub async fn send_note(&self, note: Note) -> Result<()> {
         self.emit(Event::SendNote(note)).await?;
         Ok(())
     }
 
+    // Send the revision list for a note
+    pub async fn send_history(&self, id: String, revisions: Vec<Revision>) -> Result<()> {
+        self.emit(Event::NoteHistory(id, revisions)).await?;
+        Ok(())
+    }
+
+    // Send an old version of a note
+    pub async fn send_revision(&self, hash: Hash, note: Note) -> Result<()> {
+        self.emit(Event::SendRevision(hash, note)).await?;
+        Ok(())
+    }
+
+    // Search results for the search panel
+    pub async fn send_search(&self, query: String, hits: Vec<SearchHit>) -> Result<()> {
+        self.emit(Event::SearchResults(query, hits)).await?;
+        Ok(())
+    }
+
+    // Notes that link to this one
+    pub async fn send_backlinks(&self, id: String, backlinks: Vec<String>) -> Result<()> {
+        self.emit(Event::Backlinks(id, backlinks)).await?;
+        Ok(())
+    }
+
+    // A file was stored , here is the markdown for it
+    pub async fn attached(&self, markdown: String) -> Result<()> {
+        self.emit(Event::Attached(markdown)).await?;
+        Ok(())
+    }
+
+    // Attachment bytes for the viewer
+    pub async fn send_attachment(&self, url: String, bytes: Bytes) -> Result<()> {
+        self.emit(Event::Attachment(url, bytes)).await?;
+        Ok(())
+    }
+
+    // Snapshot list for the recovery view
+    pub async fn send_snapshots(&self, snapshots: Vec<Snapshot>) -> Result<()> {
+        self.emit(Event::Snapshots(snapshots)).await?;
+        Ok(())
+    }
+
+    // Hidden notes for the trash view
+    pub async fn send_trash(&self, trash: Vec<Trashed>) -> Result<()> {
+        self.emit(Event::Trash(trash)).await?;
+        Ok(())
+    }
+
+    // The notes inside a snapshot
+    pub async fn send_snapshot(&self, hash: Hash, notes: Vec<Note>) -> Result<()> {
+        self.emit(Event::SnapshotNotes(hash, notes)).await?;
+        Ok(())
+    }
+
+    // What changed between two snapshots or revisions
+    pub async fn send_diff(&self, title: String, changes: Vec<NoteChange>) -> Result<()> {
+        self.emit(Event::Diff(title, changes)).await?;
+        Ok(())
+    }
+
+    // A note was changed by a remote peer
+    pub async fn remote_change(&self, id: String) -> Result<()> {
+        self.emit(Event::RemoteChange(id)).await?;
+        Ok(())
+    }
+
+    // Sync supervisor news for a doc
+    pub async fn sync_status(&self, key: String, status: SyncStatus) -> Result<()> {
+        self.emit(Event::SyncStatus(key, status)).await?;
+        Ok(())
+    }
+
+    // Answer to a ping
+    pub async fn pong(&self) -> Result<()> {
+        self.emit(Event::Pong).await?;
+        Ok(())
+    }
+
+    // Last thing the worker says
+    pub async fn stopped(&self) -> Result<()> {
+        self.emit(Event::Stopped).await?;
+        Ok(())
+    }
+
     // Send the share ticket up to the gui
     pub async fn share_ticket(&self, share_ticket: String) -> Result<()> {
         self.emit(Event::SendShareTicket(share_ticket)).await?;
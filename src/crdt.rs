@@ -0,0 +1,434 @@
+// Character level CRDT for collaborative notes
+// This is a replicated growable array (RGA)
+// every character gets an id and remembers what it was inserted after,
+// deletes just leave a tombstone.
+// Merging two states is a union , so peers can merge in any order
+// and end up with the same text.
+//
+// The order is a tree walk , each character's children ( the ones inserted
+// after it ) come straight after it , newest first. Children always have a
+// bigger counter than their parent so a fresh insert lands right behind it.
+//
+// Tombstones can't be dropped , a peer that has not seen the delete yet
+// may still insert after one. They cost an id and no text in the
+// stored state , see Run.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use iroh_docs::AuthorId;
+use serde::{Deserialize, Serialize};
+
+// what a deleted character holds , the text is gone
+const TOMBSTONE: char = '\0';
+
+// Unique id for a character
+// counter is a lamport clock , site comes from the author
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CharId {
+    counter: u64,
+    site: u64,
+}
+
+// A single character in the sequence
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Char {
+    id: CharId,
+    after: Option<CharId>,
+    ch: char,
+    deleted: bool,
+}
+
+// The compacted state , kept in document order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CrdtText {
+    chars: Vec<Char>,
+}
+
+// Characters one site typed in a row , stored together.
+// ids run from c up , each one after the one before ( the first after a ).
+// A run is all live ( the text ) or all deleted ( just the count ).
+#[derive(Serialize, Deserialize)]
+struct Run {
+    c: u64,
+    s: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    a: Option<(u64, u64)>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    t: String,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    d: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+// What goes in the blob , the first version was a json object per character.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Runs { runs: Vec<Run> },
+    Chars { chars: Vec<Char> },
+}
+
+#[derive(Serialize)]
+struct StoredRuns<'a> {
+    runs: &'a [Run],
+}
+
+// Authors make the site ids
+pub fn site_id(author: &AuthorId) -> u64 {
+    let bytes = author.as_bytes();
+    let mut site = [0u8; 8];
+    site.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(site)
+}
+
+impl CrdtText {
+    // Start from a plain note
+    pub fn from_text(text: &str, site: u64) -> Self {
+        let mut crdt = Self::default();
+        crdt.edit(text, site);
+        crdt
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let stored: Stored = serde_json::from_slice(bytes).context("invalid crdt state")?;
+        let chars = match stored {
+            Stored::Runs { runs } => expand(runs)?,
+            Stored::Chars { mut chars } => {
+                for c in chars.iter_mut().filter(|c| c.deleted) {
+                    c.ch = TOMBSTONE;
+                }
+                chars
+            }
+        };
+        Ok(Self { chars })
+    }
+
+    pub fn as_bytes(&self) -> Result<Bytes> {
+        let runs = compact(&self.chars);
+        let buf = serde_json::to_vec(&StoredRuns { runs: &runs })?;
+        Ok(buf.into())
+    }
+
+    // The visible text
+    pub fn text(&self) -> String {
+        self.chars
+            .iter()
+            .filter(|c| !c.deleted)
+            .map(|c| c.ch)
+            .collect()
+    }
+
+    // Turn a whole text replacement into inserts and deletes
+    // only the changed middle section is touched.
+    pub fn edit(&mut self, new_text: &str, site: u64) {
+        let visible: Vec<usize> = self
+            .chars
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.deleted)
+            .map(|(pos, _)| pos)
+            .collect();
+        let old: Vec<char> = visible.iter().map(|pos| self.chars[*pos].ch).collect();
+        let new: Vec<char> = new_text.chars().collect();
+
+        // common prefix and suffix
+        let prefix = old
+            .iter()
+            .zip(new.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        // tombstone the removed section
+        for pos in &visible[prefix..old.len() - suffix] {
+            let c = &mut self.chars[*pos];
+            c.deleted = true;
+            c.ch = TOMBSTONE;
+        }
+
+        // the new section goes right after the last kept character ,
+        // its ids are bigger than anything there so it goes first.
+        let (mut after, pos) = match prefix {
+            0 => (None, 0),
+            n => (Some(self.chars[visible[n - 1]].id), visible[n - 1] + 1),
+        };
+        let mut inserted = Vec::new();
+        let counters = self.max_counter() + 1..;
+        for (counter, ch) in counters.zip(&new[prefix..new.len() - suffix]) {
+            let id = CharId { counter, site };
+            inserted.push(Char {
+                id,
+                after,
+                ch: *ch,
+                deleted: false,
+            });
+            after = Some(id);
+        }
+        self.chars.splice(pos..pos, inserted);
+    }
+
+    // Fold another state into this one.
+    // Deletes are applied in place , new characters mean a fresh walk.
+    pub fn merge(&mut self, other: &CrdtText) {
+        let known: HashMap<CharId, usize> = self
+            .chars
+            .iter()
+            .enumerate()
+            .map(|(pos, c)| (c.id, pos))
+            .collect();
+        let mut fresh = Vec::new();
+        for c in &other.chars {
+            match known.get(&c.id) {
+                Some(pos) => {
+                    if c.deleted {
+                        let mine = &mut self.chars[*pos];
+                        mine.deleted = true;
+                        mine.ch = TOMBSTONE;
+                    }
+                }
+                None => fresh.push(c.clone()),
+            }
+        }
+        if fresh.is_empty() {
+            return;
+        }
+        let mut chars = std::mem::take(&mut self.chars);
+        chars.extend(fresh);
+        self.chars = order(chars);
+    }
+
+    fn max_counter(&self) -> u64 {
+        self.chars.iter().map(|c| c.id.counter).max().unwrap_or(0)
+    }
+}
+
+// Put a bag of characters in document order.
+// Siblings are newest first , a character whose parent is missing
+// goes at the end rather than getting lost.
+fn order(chars: Vec<Char>) -> Vec<Char> {
+    let ids: HashSet<CharId> = chars.iter().map(|c| c.id).collect();
+    let mut children: HashMap<Option<CharId>, Vec<Char>> = HashMap::new();
+    let mut orphans = Vec::new();
+    for c in chars {
+        match c.after {
+            Some(after) if !ids.contains(&after) => orphans.push(c),
+            _ => children.entry(c.after).or_default().push(c),
+        }
+    }
+    let mut roots = children.remove(&None).unwrap_or_default();
+    roots.sort_by_key(|c| Reverse(c.id));
+    orphans.sort_by_key(|c| Reverse(c.id));
+    for kids in children.values_mut() {
+        kids.sort_by_key(|c| Reverse(c.id));
+    }
+    let mut ordered = Vec::with_capacity(ids.len());
+    // a stack , so push the last sibling first
+    let mut stack: Vec<Char> = roots.into_iter().chain(orphans).rev().collect();
+    while let Some(c) = stack.pop() {
+        if let Some(kids) = children.remove(&Some(c.id)) {
+            stack.extend(kids.into_iter().rev());
+        }
+        ordered.push(c);
+    }
+    ordered
+}
+
+// Squash the characters into runs for storage
+fn compact(chars: &[Char]) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    let mut last: Option<&Char> = None;
+    for c in chars {
+        let follows = last.is_some_and(|l| {
+            c.id.site == l.id.site
+                && c.id.counter == l.id.counter + 1
+                && c.after == Some(l.id)
+                && c.deleted == l.deleted
+        });
+        match runs.last_mut() {
+            Some(run) if follows => {
+                if c.deleted {
+                    run.d += 1;
+                } else {
+                    run.t.push(c.ch);
+                }
+            }
+            _ => runs.push(Run {
+                c: c.id.counter,
+                s: c.id.site,
+                a: c.after.map(|a| (a.counter, a.site)),
+                t: match c.deleted {
+                    true => String::new(),
+                    false => c.ch.to_string(),
+                },
+                d: c.deleted as u64,
+            }),
+        }
+        last = Some(c);
+    }
+    runs
+}
+
+// And back out again
+fn expand(runs: Vec<Run>) -> Result<Vec<Char>> {
+    let mut chars = Vec::new();
+    for run in runs {
+        anyhow::ensure!(run.t.is_empty() || run.d == 0, "crdt run is both live and deleted");
+        let mut after = run.a.map(|(counter, site)| CharId { counter, site });
+        let live = run.t.chars().map(|ch| (ch, false));
+        let dead = (0..run.d).map(|_| (TOMBSTONE, true));
+        for (n, (ch, deleted)) in live.chain(dead).enumerate() {
+            let id = CharId {
+                counter: run.c + n as u64,
+                site: run.s,
+            };
+            chars.push(Char {
+                id,
+                after,
+                ch,
+                deleted,
+            });
+            after = Some(id);
+        }
+    }
+    Ok(chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: u64 = 1;
+    const B: u64 = 2;
+
+    // merge both ways round , they have to agree
+    fn converge(a: &CrdtText, b: &CrdtText) -> CrdtText {
+        let mut ab = a.clone();
+        ab.merge(b);
+        let mut ba = b.clone();
+        ba.merge(a);
+        assert_eq!(ab.text(), ba.text());
+        assert_eq!(ab, ba);
+        ab
+    }
+
+    #[test]
+    fn concurrent_inserts_converge() {
+        let base = CrdtText::from_text("hello world", A);
+        let mut a = base.clone();
+        a.edit("hello brave world", A);
+        let mut b = base.clone();
+        b.edit("hello new world", B);
+        let merged = converge(&a, &b);
+        let text = merged.text();
+        assert!(text.contains("brave "), "{text}");
+        assert!(text.contains("new "), "{text}");
+        assert!(text.starts_with("hello ") && text.ends_with("world"), "{text}");
+    }
+
+    #[test]
+    fn same_spot_is_ordered_the_same_everywhere() {
+        let base = CrdtText::from_text("ac", A);
+        let mut a = base.clone();
+        a.edit("abc", A);
+        let mut b = base.clone();
+        b.edit("axc", B);
+        // same counter , the bigger site goes first
+        assert_eq!(converge(&a, &b).text(), "axbc");
+    }
+
+    #[test]
+    fn delete_and_insert_converge() {
+        let base = CrdtText::from_text("one two three", A);
+        let mut a = base.clone();
+        a.edit("one three", A);
+        let mut b = base.clone();
+        b.edit("one twoo three", B);
+        let merged = converge(&a, &b);
+        // a's edit keeps the first t and deletes "wo t" ( the diff is
+        // prefix / suffix , not by word ) , the o inserted in there survives
+        assert_eq!(merged.text(), "one tohree");
+    }
+
+    #[test]
+    fn deletes_on_both_sides() {
+        let base = CrdtText::from_text("abcdef", A);
+        let mut a = base.clone();
+        a.edit("abef", A);
+        let mut b = base.clone();
+        b.edit("adef", B);
+        assert_eq!(converge(&a, &b).text(), "aef");
+    }
+
+    #[test]
+    fn merge_is_idempotent() {
+        let mut a = CrdtText::from_text("some text", A);
+        a.edit("some more text", A);
+        let before = a.clone();
+        a.merge(&before);
+        assert_eq!(a, before);
+    }
+
+    #[test]
+    fn three_way_in_any_order() {
+        let base = CrdtText::from_text("x", A);
+        let mut a = base.clone();
+        a.edit("ax", A);
+        let mut b = base.clone();
+        b.edit("xb", B);
+        let mut c = base.clone();
+        c.edit("", 3);
+        let mut first = a.clone();
+        first.merge(&b);
+        first.merge(&c);
+        let mut second = c.clone();
+        second.merge(&b);
+        second.merge(&a);
+        assert_eq!(first, second);
+        assert_eq!(first.text(), "ab");
+    }
+
+    #[test]
+    fn tombstones_round_trip_without_text() {
+        let mut a = CrdtText::from_text("keep this, drop that", A);
+        a.edit("keep this", A);
+        let bytes = a.as_bytes().unwrap();
+        let back = CrdtText::from_bytes(&bytes).unwrap();
+        assert_eq!(back, a);
+        assert_eq!(back.text(), "keep this");
+        let stored = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(!stored.contains("drop"), "{stored}");
+    }
+
+    #[test]
+    fn runs_are_compact() {
+        let text = "a fairly long line of text typed in one go ".repeat(20);
+        let crdt = CrdtText::from_text(&text, A);
+        let bytes = crdt.as_bytes().unwrap();
+        // one run , not an object per character
+        assert!(bytes.len() < text.len() + 100, "{} bytes", bytes.len());
+    }
+
+    #[test]
+    fn reads_the_old_encoding() {
+        let old = r#"{"chars":[
+            {"id":{"counter":1,"site":1},"after":null,"ch":"h","deleted":false},
+            {"id":{"counter":2,"site":1},"after":{"counter":1,"site":1},"ch":"x","deleted":true},
+            {"id":{"counter":3,"site":1},"after":{"counter":2,"site":1},"ch":"i","deleted":false}
+        ]}"#;
+        let crdt = CrdtText::from_bytes(old.as_bytes()).unwrap();
+        assert_eq!(crdt.text(), "hi");
+        let back = CrdtText::from_bytes(&crdt.as_bytes().unwrap()).unwrap();
+        assert_eq!(back, crdt);
+    }
+}
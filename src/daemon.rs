@@ -0,0 +1,135 @@
+// Headless worker , the always on "mothership" peer
+// Same config and store as the gui , opens every doc set and keeps them synced.
+// No window , everything goes to the tracing log.
+// SIGTERM or ctrl-c shuts the worker down cleanly.
+//   liminal-doc daemon
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use tracing::{error, info, warn};
+
+use crate::app::APP_NAME;
+use crate::comms::{Command, Config, Event, MessageType};
+use crate::worker::{Worker, WorkerHandle};
+
+// how long the worker gets to close the stores
+const SHUTDOWN_WAIT: Duration = Duration::from_secs(10);
+
+pub fn run() -> Result<()> {
+    let config: Config = confy::load(APP_NAME, None)?;
+    let doc_id = match &config.doc_key {
+        Some(key) => key.clone(),
+        None => match config.doc_sets.first() {
+            Some(doc_set) => doc_set.doc_key.clone(),
+            None => bail!("no doc set up yet , join or make one first"),
+        },
+    };
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(serve(config, doc_id))
+}
+
+async fn serve(config: Config, doc_id: String) -> Result<()> {
+    // set up before anything starts , so an early signal is not lost
+    let terminate = terminated()?;
+    tokio::pin!(terminate);
+    let handle = Worker::spawn(config);
+    loop {
+        let event = next_event(&handle).await?;
+        if matches!(event, Event::SetReady) {
+            break;
+        }
+        log_event(event);
+    }
+    send(&handle, Command::DocId(doc_id)).await?;
+    info!("daemon running");
+
+    loop {
+        tokio::select! {
+            event = next_event(&handle) => log_event(event?),
+            _ = &mut terminate => {
+                info!("SIGTERM");
+                break;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("interrupted");
+                break;
+            }
+        }
+    }
+
+    // let the worker close up , don't hang about forever
+    send(&handle, Command::Shutdown).await?;
+    let stopped = tokio::time::timeout(SHUTDOWN_WAIT, async {
+        loop {
+            match next_event(&handle).await? {
+                Event::Stopped => return Ok::<(), anyhow::Error>(()),
+                event => log_event(event),
+            }
+        }
+    })
+    .await;
+    match stopped {
+        Ok(result) => result?,
+        Err(_) => warn!("worker did not stop in time"),
+    }
+    info!("daemon stopped");
+    Ok(())
+}
+
+// Resolves on SIGTERM , never off unix
+#[cfg(unix)]
+fn terminated() -> Result<impl Future<Output = ()>> {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        terminate.recv().await;
+    })
+}
+
+#[cfg(not(unix))]
+fn terminated() -> Result<impl Future<Output = ()>> {
+    Ok(std::future::pending())
+}
+
+async fn next_event(handle: &WorkerHandle) -> Result<Event> {
+    handle
+        .event_rx
+        .recv()
+        .await
+        .map_err(|_| anyhow!("worker stopped"))
+}
+
+async fn send(handle: &WorkerHandle, command: Command) -> Result<()> {
+    handle
+        .command_tx
+        .send(command)
+        .await
+        .map_err(|_| anyhow!("worker is not running"))
+}
+
+// Nobody is watching , messages go to the log
+// and the config is saved the way the gui would.
+fn log_event(event: Event) {
+    match event {
+        Event::Message(message) => match message.mtype {
+            MessageType::Good | MessageType::Info => info!("{}", message.text),
+            MessageType::Error => error!("{}", message.text),
+        },
+        Event::SendConfig(config) => {
+            if let Err(e) = confy::store(APP_NAME, None, &config) {
+                warn!("config not saved , {e}");
+            }
+        }
+        Event::RemoteChange(id) => info!("remote change {id}"),
+        Event::SyncStatus(key, status) => info!(
+            "sync {key} {} , {} peers , {} pending",
+            status.state,
+            status.connected(),
+            status.pending
+        ),
+        _ => {}
+    }
+}
@@ -0,0 +1,259 @@
+// Differences between two sets of notes or two versions of one note
+// Line level , longest common subsequence after trimming the ends.
+// Used by the gui diff view and the command line.
+
+use std::collections::BTreeMap;
+
+use crate::notes::Note;
+
+// unchanged lines kept around a change
+pub const CONTEXT: usize = 3;
+// past this the middle is too big to line up , it is shown replaced wholesale
+const MAX_CELLS: usize = 4_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+#[derive(Clone, Debug)]
+pub enum NoteChange {
+    Added(String),
+    Removed(String),
+    Modified(String, Vec<DiffLine>),
+}
+
+impl NoteChange {
+    pub fn id(&self) -> &str {
+        match self {
+            NoteChange::Added(id) | NoteChange::Removed(id) | NoteChange::Modified(id, _) => id,
+        }
+    }
+}
+
+// Compare two texts line by line
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // common ends , most edits are small
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut lines: Vec<DiffLine> = old[..prefix]
+        .iter()
+        .map(|l| DiffLine::Same(l.to_string()))
+        .collect();
+    lines.extend(diff_middle(old_mid, new_mid));
+    lines.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|l| DiffLine::Same(l.to_string())),
+    );
+    lines
+}
+
+// The changed section , classic lcs table
+fn diff_middle(old: &[&str], new: &[&str]) -> Vec<DiffLine> {
+    let removed = old.iter().map(|l| DiffLine::Removed(l.to_string()));
+    let added = new.iter().map(|l| DiffLine::Added(l.to_string()));
+    if old.is_empty() || new.is_empty() || old.len() * new.len() > MAX_CELLS {
+        return removed.chain(added).collect();
+    }
+    // table[i][j] = lcs length of old[i..] and new[j..]
+    let width = new.len() + 1;
+    let mut table = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            table[i * width + j] = if old[i] == new[j] {
+                table[(i + 1) * width + j + 1] + 1
+            } else {
+                table[(i + 1) * width + j].max(table[i * width + j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if table[(i + 1) * width + j] >= table[i * width + j + 1] {
+            lines.push(DiffLine::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|l| DiffLine::Removed(l.to_string())));
+    lines.extend(new[j..].iter().map(|l| DiffLine::Added(l.to_string())));
+    lines
+}
+
+// Compare two sets of notes by id
+pub fn diff_notes(old: &[Note], new: &[Note]) -> Vec<NoteChange> {
+    let old: BTreeMap<&str, &Note> = old.iter().map(|n| (n.id.as_str(), n)).collect();
+    let new: BTreeMap<&str, &Note> = new.iter().map(|n| (n.id.as_str(), n)).collect();
+    let mut changes = Vec::new();
+    for (id, note) in old.iter() {
+        match new.get(id) {
+            None => changes.push(NoteChange::Removed(id.to_string())),
+            Some(other) if other.text != note.text => {
+                // only line endings moved , nothing to show
+                let lines = diff_lines(&note.text, &other.text);
+                if lines.iter().any(|l| !matches!(l, DiffLine::Same(_))) {
+                    changes.push(NoteChange::Modified(id.to_string(), lines));
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    for id in new.keys() {
+        if !old.contains_key(id) {
+            changes.push(NoteChange::Added(id.to_string()));
+        }
+    }
+    changes.sort_by(|a, b| a.id().cmp(b.id()));
+    changes
+}
+
+// Only the changes with a little context around them
+// None marks a gap of unchanged lines.
+pub fn with_context(lines: &[DiffLine], context: usize) -> Vec<Option<&DiffLine>> {
+    let changed: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| !matches!(l, DiffLine::Same(_)))
+        .map(|(pos, _)| pos)
+        .collect();
+    let mut keep = vec![false; lines.len()];
+    for pos in changed {
+        let start = pos.saturating_sub(context);
+        let end = (pos + context + 1).min(lines.len());
+        keep[start..end].iter_mut().for_each(|k| *k = true);
+    }
+    let mut out = Vec::new();
+    let mut gap = false;
+    for (line, keep) in lines.iter().zip(keep) {
+        if keep {
+            out.push(Some(line));
+            gap = false;
+        } else if !gap {
+            out.push(None);
+            gap = true;
+        }
+    }
+    out
+}
+
+// Plain text for the command line , unified diff style
+pub fn unified(changes: &[NoteChange]) -> String {
+    let mut out = String::new();
+    for change in changes {
+        match change {
+            NoteChange::Added(id) => out.push_str(&format!("added    {id}\n")),
+            NoteChange::Removed(id) => out.push_str(&format!("removed  {id}\n")),
+            NoteChange::Modified(id, lines) => {
+                out.push_str(&format!("modified {id}\n--- {id}\n+++ {id}\n"));
+                for line in with_context(lines, CONTEXT) {
+                    match line {
+                        Some(DiffLine::Same(l)) => out.push_str(&format!(" {l}\n")),
+                        Some(DiffLine::Added(l)) => out.push_str(&format!("+{l}\n")),
+                        Some(DiffLine::Removed(l)) => out.push_str(&format!("-{l}\n")),
+                        None => out.push_str("@@\n"),
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, text: &str) -> Note {
+        let mut note = Note::missing_note(id.to_string());
+        note.text = text.to_string();
+        note
+    }
+
+    #[test]
+    fn lines_in_order() {
+        let lines = diff_lines("a\nb\nc\n", "a\nx\nc\nd\n");
+        assert_eq!(
+            lines,
+            [
+                DiffLine::Same("a".into()),
+                DiffLine::Removed("b".into()),
+                DiffLine::Added("x".into()),
+                DiffLine::Same("c".into()),
+                DiffLine::Added("d".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn context_leaves_gaps() {
+        let old: String = (0..20).map(|n| format!("{n}\n")).collect();
+        let new = old.replace("10\n", "ten\n");
+        let lines = diff_lines(&old, &new);
+        let shown = with_context(&lines, 2);
+        // gap , two before , the change , two after , gap
+        assert_eq!(shown.len(), 1 + 2 + 2 + 2 + 1);
+        assert!(shown[0].is_none());
+        assert_eq!(shown[1], Some(&DiffLine::Same("8".into())));
+        assert_eq!(shown[3], Some(&DiffLine::Removed("10".into())));
+        assert_eq!(shown[4], Some(&DiffLine::Added("ten".into())));
+        assert!(shown[7].is_none());
+    }
+
+    #[test]
+    fn close_changes_share_a_hunk() {
+        let old = "1\n2\n3\n4\n5\n";
+        let new = "one\n2\n3\n4\nfive\n";
+        let lines = diff_lines(old, new);
+        assert!(with_context(&lines, CONTEXT).iter().all(|l| l.is_some()));
+    }
+
+    #[test]
+    fn notes_added_removed_modified() {
+        let old = [note("a", "same"), note("b", "old"), note("c", "gone")];
+        let new = [note("a", "same"), note("b", "new"), note("d", "fresh")];
+        let changes = diff_notes(&old, &new);
+        let ids: Vec<&str> = changes.iter().map(|c| c.id()).collect();
+        assert_eq!(ids, ["b", "c", "d"]);
+        assert!(matches!(changes[0], NoteChange::Modified(_, _)));
+        assert!(matches!(changes[1], NoteChange::Removed(_)));
+        assert!(matches!(changes[2], NoteChange::Added(_)));
+    }
+
+    #[test]
+    fn trailing_newline_is_not_a_change() {
+        let old = [note("a", "text")];
+        let new = [note("a", "text\n")];
+        assert!(diff_notes(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn unified_output() {
+        let changes = diff_notes(&[note("a", "x\ny\n")], &[note("a", "x\nz\n")]);
+        assert_eq!(unified(&changes), "modified a\n--- a\n+++ a\n x\n-y\n+z\n");
+    }
+}
@@ -0,0 +1,147 @@
+// Bulk import of a folder of markdown files
+// The relative path becomes the note id ( projects/liminal/roadmap.md -> projects/liminal/roadmap )
+// file times become created / updated , front matter can override them.
+// This is blocking file work , run it off the async threads.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Result, bail, ensure};
+use walkdir::WalkDir;
+
+use crate::notes::{Note, NoteKind, normalize_path, parse_timestamp, split_front_matter};
+
+// bigger than this is probably not a note
+const MAX_IMPORT_SIZE: u64 = 1024 * 1024;
+const EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+// What came out of a folder
+pub struct Scan {
+    pub notes: Vec<Note>,
+    // file and why it was left out
+    pub skipped: Vec<(PathBuf, String)>,
+    // file and the front matter keys we had nowhere to keep
+    pub dropped: Vec<(PathBuf, Vec<String>)>,
+}
+
+pub fn scan(dir: &Path) -> Result<Scan> {
+    ensure!(dir.is_dir(), "{} is not a folder", dir.display());
+    let mut scan = Scan {
+        notes: Vec::new(),
+        skipped: Vec::new(),
+        dropped: Vec::new(),
+    };
+    // id -> the file it came from
+    let mut seen: HashMap<String, PathBuf> = HashMap::new();
+    for entry in WalkDir::new(dir).follow_links(false).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+                scan.skipped.push((path, e.to_string()));
+                continue;
+            }
+        };
+        let path = entry.path();
+        if !entry.file_type().is_file() || !is_markdown(path) {
+            continue;
+        }
+        match read_note(dir, path) {
+            // two files can end up with one id ( front matter , or a.md and a.markdown )
+            Ok((note, dropped)) => match seen.get(&note.id) {
+                Some(first) => {
+                    let why = format!("same id as {}", first.display());
+                    scan.skipped.push((path.to_path_buf(), why));
+                }
+                None => {
+                    seen.insert(note.id.clone(), path.to_path_buf());
+                    scan.notes.push(note);
+                    if !dropped.is_empty() {
+                        scan.dropped.push((path.to_path_buf(), dropped));
+                    }
+                }
+            },
+            Err(e) => scan.skipped.push((path.to_path_buf(), e.to_string())),
+        }
+    }
+    Ok(scan)
+}
+
+fn is_markdown(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+// One file into a note , and the front matter keys it lost
+fn read_note(dir: &Path, path: &Path) -> Result<(Note, Vec<String>)> {
+    let meta = path.metadata()?;
+    ensure!(
+        meta.len() <= MAX_IMPORT_SIZE,
+        "too large ({} bytes)",
+        meta.len()
+    );
+    let bytes = std::fs::read(path)?;
+    let Ok(text) = String::from_utf8(bytes) else {
+        bail!("not utf8");
+    };
+    // the id is the path inside the folder without the extension
+    let relative = path.strip_prefix(dir)?.with_extension("");
+    let parts: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    let id = normalize_path(&parts.join("/"));
+    ensure!(!id.is_empty(), "no name");
+
+    let updated = meta.modified().map(unix_time).unwrap_or(0);
+    // not every filesystem knows when a file was made
+    let created = meta.created().map(unix_time).unwrap_or(updated);
+    let mut note = Note {
+        id,
+        text: text.clone(),
+        created,
+        updated,
+        is_delete: false,
+        kind: NoteKind::Plain,
+    };
+
+    // the front matter never goes in the note , keys we don't know are reported
+    let mut dropped = Vec::new();
+    if let Some((pairs, body)) = split_front_matter(&text) {
+        for (key, value) in pairs {
+            match key.as_str() {
+                "id" => {
+                    let id = normalize_path(&value);
+                    if !id.is_empty() {
+                        note.id = id;
+                    }
+                }
+                "created" | "date" => {
+                    if let Some(time) = parse_timestamp(&value) {
+                        note.created = time;
+                    }
+                }
+                "updated" | "modified" => {
+                    if let Some(time) = parse_timestamp(&value) {
+                        note.updated = time;
+                    }
+                }
+                _ => dropped.push(key),
+            }
+        }
+        note.text = body.to_string();
+    }
+    // a file can't be updated before it was made
+    note.updated = note.updated.max(note.created);
+    Ok((note, dropped))
+}
+
+pub fn unix_time(time: SystemTime) -> i64 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(_) => 0,
+    }
+}
@@ -0,0 +1,211 @@
+// Wiki style links between notes
+// [[Note Name]] or [[Note Name|shown text]]
+// The viewer gets them rewritten as markdown links with a note: scheme,
+// the worker keeps a backlink index so a note knows who links to it.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::notes::normalize_path;
+
+// Url scheme for links inside the viewer
+pub const NOTE_SCHEME: &str = "note:";
+
+// A link found in the text
+struct WikiLink {
+    // byte range of the whole [[...]]
+    start: usize,
+    end: usize,
+    target: String,
+    label: String,
+}
+
+// Find the links , skipping fenced code blocks
+fn find_links(text: &str) -> Vec<WikiLink> {
+    let mut links = Vec::new();
+    let mut in_fence = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if !in_fence {
+            let mut pos = 0;
+            while let Some(open) = line[pos..].find("[[") {
+                let open = pos + open;
+                let Some(close) = line[open + 2..].find("]]") else {
+                    break;
+                };
+                let close = open + 2 + close;
+                let inner = &line[open + 2..close];
+                let (target, label) = match inner.split_once('|') {
+                    Some((target, label)) => (target, label.trim()),
+                    None => (inner, inner.trim()),
+                };
+                let target = normalize_path(target);
+                if !target.is_empty() {
+                    links.push(WikiLink {
+                        start: offset + open,
+                        end: offset + close + 2,
+                        target,
+                        label: label.to_string(),
+                    });
+                }
+                pos = close + 2;
+            }
+        }
+        offset += line.len();
+    }
+    links
+}
+
+// Every note this text links to
+pub fn wiki_links(text: &str) -> BTreeSet<String> {
+    find_links(text).into_iter().map(|l| l.target).collect()
+}
+
+// Rewrite [[links]] into markdown links for the viewer
+pub fn render_links(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut pos = 0;
+    for link in find_links(text) {
+        out.push_str(&text[pos..link.start]);
+        out.push_str(&format!("[{}](<{}{}>)", link.label, NOTE_SCHEME, link.target));
+        pos = link.end;
+    }
+    out.push_str(&text[pos..]);
+    out
+}
+
+// Note a link click points at , None for normal urls
+pub fn link_target(url: &str) -> Option<String> {
+    url.strip_prefix(NOTE_SCHEME).map(normalize_path)
+}
+
+// renames to follow before giving up , same as the notes
+const MAX_REDIRECTS: usize = 10;
+
+// Who links to whom
+// kept up to date with the search index in the worker.
+// Links to a renamed note still point at the old id ,
+// so the renames are kept as well and followed when looking up backlinks.
+#[derive(Default)]
+pub struct LinkIndex {
+    forward: HashMap<String, BTreeSet<String>>,
+    moved: HashMap<String, String>,
+}
+
+impl LinkIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.forward.clear();
+        self.moved.clear();
+    }
+
+    // A note was renamed
+    pub fn moved(&mut self, from: &str, to: &str) {
+        self.moved.insert(from.to_string(), to.to_string());
+        // a note can move back to an old name
+        self.moved.remove(to);
+    }
+
+    // Where a link target lives now
+    fn resolve<'a>(&'a self, target: &'a str) -> &'a str {
+        let mut target = target;
+        for _ in 0..MAX_REDIRECTS {
+            match self.moved.get(target) {
+                Some(to) => target = to,
+                None => break,
+            }
+        }
+        target
+    }
+
+    pub fn index(&mut self, id: &str, text: &str) {
+        let links = wiki_links(text);
+        if links.is_empty() {
+            self.forward.remove(id);
+        } else {
+            self.forward.insert(id.to_string(), links);
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.forward.remove(id);
+    }
+
+    // Notes that link to this one
+    pub fn backlinks(&self, id: &str) -> Vec<String> {
+        let mut found: Vec<String> = self
+            .forward
+            .iter()
+            .filter(|(from, targets)| {
+                from.as_str() != id && targets.iter().any(|t| self.resolve(t) == id)
+            })
+            .map(|(from, _)| from.clone())
+            .collect();
+        found.sort();
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_links_with_labels() {
+        let text = "see [[Other Note]] and [[a/b|the b note]] here";
+        assert_eq!(
+            render_links(text),
+            format!(
+                "see [Other Note](<{NOTE_SCHEME}{}>) and [the b note](<{NOTE_SCHEME}{}>) here",
+                normalize_path("Other Note"),
+                normalize_path("a/b"),
+            )
+        );
+    }
+
+    #[test]
+    fn skips_code_fences() {
+        let text = "```\n[[not a link]]\n```\n[[real]]\n";
+        let links = wiki_links(text);
+        assert_eq!(links.len(), 1);
+        assert!(links.contains(&normalize_path("real")));
+        assert!(render_links(text).starts_with("```\n[[not a link]]\n```\n["));
+    }
+
+    #[test]
+    fn leaves_broken_links_alone() {
+        for text in ["open [[ but never closed", "[[]] is empty", "[[ / ]] too"] {
+            assert_eq!(render_links(text), text);
+        }
+    }
+
+    #[test]
+    fn link_targets_only_for_notes() {
+        assert_eq!(link_target("https://example.com"), None);
+        let url = format!("{NOTE_SCHEME}some/note");
+        assert_eq!(link_target(&url), Some(normalize_path("some/note")));
+    }
+
+    #[test]
+    fn backlinks_follow_renames() {
+        let mut links = LinkIndex::new();
+        links.index("a", "[[old]]");
+        links.index("b", "[[new]]");
+        links.index("c", "nothing here");
+        links.moved("old", "new");
+        assert_eq!(links.backlinks("new"), ["a", "b"]);
+        assert!(links.backlinks("old").is_empty());
+    }
+
+    #[test]
+    fn no_backlink_to_itself() {
+        let mut links = LinkIndex::new();
+        links.index("a", "[[a]]");
+        assert!(links.backlinks("a").is_empty());
+    }
+}
@@ -4,15 +4,39 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] 
 
 mod app;
+mod cli;
 mod comms;
 mod worker;
 mod about;
+mod api;
+mod attach;
+mod crdt;
+mod daemon;
+mod diff;
+mod import;
+mod links;
+mod mirror;
 mod notes;
+mod search;
+mod sync;
 
 use app::App;
 use eframe::NativeOptions;
 
 fn main() -> eframe::Result {
+    // a cli command is a run with no window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if cli::is_cli(&args) {
+        #[cfg(windows)]
+        attach_console();
+        // keep stdout for the results
+        tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+        if let Err(e) = cli::run(&args) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     tracing_subscriber::fmt::init();
     let mut options = NativeOptions::default();
     options.viewport = options
@@ -23,3 +47,18 @@ fn main() -> eframe::Result {
         .with_drag_and_drop(true); // So cool !!
     App::run(options)
 }
+
+// The release build has no console of its own on windows ,
+// borrow the one the cli was started from so the output shows.
+#[cfg(windows)]
+fn attach_console() {
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn AttachConsole(process: u32) -> i32;
+    }
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    // no parent console ( started from explorer ) , nothing to do
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
@@ -0,0 +1,289 @@
+// Live mirror of the notes in a local folder
+// every note is a <id>.md file , edit them in whatever you like.
+// The worker polls the folder , changed files become note updates
+// and note changes (local or remote) are written back out.
+//
+// Loops are stopped by remembering the content hash each file was
+// last synced at , writing what is already there does nothing and
+// reading back what we wrote does nothing.
+// If a note changes while its file has unsynced edits the file is left
+// alone and the incoming version goes next to it as <id>.conflict-<time>.md ,
+// the file only goes back in once it is saved again.
+// Deleting a file does not delete the note.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use chrono::Utc;
+use iroh_blobs::Hash;
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::import::unix_time;
+use crate::notes::{Note, normalize_path};
+
+const CONFLICT_MARK: &str = ".conflict-";
+
+// What we last agreed with the file on
+struct Synced {
+    hash: Hash,
+    modified: Option<SystemTime>,
+}
+
+pub enum Written {
+    Same,
+    Written,
+    // the file had edits of its own , the note went here instead
+    Conflict(PathBuf),
+}
+
+// A file that changed , it only counts as synced once the note took it
+pub struct FileEdit {
+    pub id: String,
+    pub text: String,
+    hash: Hash,
+    modified: SystemTime,
+}
+
+pub struct Mirror {
+    dir: PathBuf,
+    synced: HashMap<String, Synced>,
+    // files the note would not take , left alone until they change again
+    failed: HashMap<String, SystemTime>,
+}
+
+// Where a file for a note goes , folder ids become folders
+// anything trying to climb out of the folder is dropped.
+pub fn note_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+    for part in name.split('/') {
+        if part.is_empty() || part == "." || part == ".." {
+            return None;
+        }
+        path.push(part);
+    }
+    Some(path)
+}
+
+impl Mirror {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            synced: HashMap::new(),
+            failed: HashMap::new(),
+        }
+    }
+
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        note_path(&self.dir, &format!("{id}.md"))
+    }
+
+    // Bring the whole folder in line with the notes.
+    // Files we have never seen that are newer than the note are kept,
+    // they were edited while we were not running and the next scan picks them up.
+    pub async fn sync(&mut self, notes: &[Note]) -> Result<Vec<PathBuf>> {
+        let mut conflicts = Vec::new();
+        for note in notes {
+            if !self.synced.contains_key(&note.id) {
+                if let Some(path) = self.path(&note.id) {
+                    if let Ok(meta) = tokio::fs::metadata(&path).await {
+                        let newer = match meta.modified() {
+                            Ok(modified) => unix_time(modified) > note.updated,
+                            Err(_) => false,
+                        };
+                        if newer {
+                            self.synced.insert(
+                                note.id.clone(),
+                                Synced {
+                                    hash: Hash::new(note.text.as_bytes()),
+                                    modified: None,
+                                },
+                            );
+                            continue;
+                        }
+                    }
+                }
+            }
+            if let Written::Conflict(path) = self.write(&note.id, &note.text).await? {
+                conflicts.push(path);
+            }
+        }
+        // notes that went away (hidden , moved)
+        let gone: Vec<String> = self
+            .synced
+            .keys()
+            .filter(|id| !notes.iter().any(|n| &n.id == *id))
+            .cloned()
+            .collect();
+        for id in gone {
+            self.remove(&id).await?;
+        }
+        Ok(conflicts)
+    }
+
+    // Put a note in its file , unless the file has edits we have not seen.
+    pub async fn write(&mut self, id: &str, text: &str) -> Result<Written> {
+        let Some(path) = self.path(id) else {
+            warn!("no mirror file for {id}");
+            return Ok(Written::Same);
+        };
+        let hash = Hash::new(text.as_bytes());
+        if let Ok(current) = tokio::fs::read(&path).await {
+            let current = Hash::new(&current);
+            if current == hash {
+                self.remember(id, hash, &path).await;
+                return Ok(Written::Same);
+            }
+            let dirty = match self.synced.get(id) {
+                Some(synced) => synced.hash != current,
+                // there before us and we have not looked at it yet
+                None => false,
+            };
+            if dirty {
+                let stamp = Utc::now().format("%Y%m%d-%H%M%S");
+                let conflict = path.with_extension(format!("{}{stamp}.md", &CONFLICT_MARK[1..]));
+                tokio::fs::write(&conflict, text).await?;
+                // agree with the file as it stands , it is not pushed back over
+                // the note until it is edited again and the next write goes in it.
+                self.remember(id, current, &path).await;
+                return Ok(Written::Conflict(conflict));
+            }
+        }
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // write beside and swap in , editors never see half a file
+        let temp = path.with_extension("md.tmp");
+        tokio::fs::write(&temp, text).await?;
+        tokio::fs::rename(&temp, &path).await?;
+        self.remember(id, hash, &path).await;
+        Ok(Written::Written)
+    }
+
+    // The note went away , so does the file (unless it is being edited)
+    pub async fn remove(&mut self, id: &str) -> Result<()> {
+        let Some(synced) = self.synced.remove(id) else {
+            return Ok(());
+        };
+        let Some(path) = self.path(id) else {
+            return Ok(());
+        };
+        if let Ok(current) = tokio::fs::read(&path).await {
+            if Hash::new(&current) == synced.hash {
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Files that changed since we last synced them ,
+    // call synced or failed with each once the note has been tried.
+    pub async fn scan(&mut self) -> Result<Vec<FileEdit>> {
+        let dir = self.dir.clone();
+        let files = tokio::task::spawn_blocking(move || list_files(&dir)).await?;
+        let mut changed = Vec::new();
+        for (id, path, modified) in files {
+            if let Some(synced) = self.synced.get(&id) {
+                if synced.modified == Some(modified) {
+                    continue;
+                }
+            }
+            if self.failed.get(&id) == Some(&modified) {
+                continue;
+            }
+            let text = match tokio::fs::read(&path).await {
+                Ok(bytes) => match String::from_utf8(bytes) {
+                    Ok(text) => text,
+                    Err(_) => {
+                        warn!("mirror file {} is not utf8", path.display());
+                        continue;
+                    }
+                },
+                // being written , try next time
+                Err(_) => continue,
+            };
+            let hash = Hash::new(text.as_bytes());
+            let same = match self.synced.get(&id) {
+                Some(synced) => synced.hash == hash,
+                None => false,
+            };
+            if same {
+                // touched but not changed
+                self.synced.insert(
+                    id,
+                    Synced {
+                        hash,
+                        modified: Some(modified),
+                    },
+                );
+            } else {
+                changed.push(FileEdit {
+                    id,
+                    text,
+                    hash,
+                    modified,
+                });
+            }
+        }
+        Ok(changed)
+    }
+
+    // The note has the file's text now
+    pub fn synced(&mut self, edit: &FileEdit) {
+        self.failed.remove(&edit.id);
+        self.synced.insert(
+            edit.id.clone(),
+            Synced {
+                hash: edit.hash,
+                modified: Some(edit.modified),
+            },
+        );
+    }
+
+    // The note would not take the file , try again when it changes
+    pub fn failed(&mut self, edit: &FileEdit) {
+        self.failed.insert(edit.id.clone(), edit.modified);
+    }
+
+    async fn remember(&mut self, id: &str, hash: Hash, path: &Path) {
+        let modified = match tokio::fs::metadata(path).await {
+            Ok(meta) => meta.modified().ok(),
+            Err(_) => None,
+        };
+        self.synced
+            .insert(id.to_string(), Synced { hash, modified });
+    }
+}
+
+// Every note file in the folder , (id , path , modified)
+fn list_files(dir: &Path) -> Vec<(String, PathBuf, SystemTime)> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir).follow_links(false) {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy();
+        if !entry.file_type().is_file() || !name.ends_with(".md") || name.contains(CONFLICT_MARK) {
+            continue;
+        }
+        let Ok(relative) = path.strip_prefix(dir) else {
+            continue;
+        };
+        let parts: Vec<String> = relative
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        let id = normalize_path(&parts.join("/"));
+        let Some(modified) = entry.metadata().ok().and_then(|m| m.modified().ok()) else {
+            continue;
+        };
+        if !id.is_empty() {
+            files.push((id, path.to_path_buf(), modified));
+        }
+    }
+    files
+}
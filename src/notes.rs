@@ -14,15 +14,25 @@
 // So ... when keys are written or read they need to have a null byte added
 // or removed as they come in and out of docs. Insane...
 
-use std::{cmp::Reverse, str::FromStr, sync::Arc};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+};
 
 use anyhow::{Context, Result, anyhow, bail, ensure};
 use bytes::Bytes;
-use chrono::{Local, Utc};
-use iroh::NodeAddr;
-use iroh_blobs::{BlobsProtocol, format::collection::Collection};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+use iroh::{Endpoint, NodeAddr, NodeId};
+use iroh_blobs::{
+    BlobsProtocol, Hash, HashAndFormat,
+    api::downloader::{Downloader, Shuffled},
+    format::collection::Collection,
+};
 use iroh_docs::{
-    AuthorId, DocTicket, Entry, NamespaceId,
+    AuthorId, Capability, CapabilityKind, DocTicket, Entry, NamespaceId,
     api::{
         Doc,
         protocol::{AddrInfoOptions, ShareMode},
@@ -35,9 +45,14 @@ use iroh_docs::{
 // use n0_watcher::Watcher;
 use n0_future::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tracing::warn;
 
+use crate::attach::{blob_urls, parse_blob_url};
+use crate::crdt::{CrdtText, site_id};
+
 // Individual notes
+// this is what the gui sees , the text is filled in from the body blob
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Note {
     pub id: String,
@@ -45,33 +60,282 @@ pub struct Note {
     pub created: i64,
     pub updated: i64,
     pub is_delete: bool,
+    #[serde(default)]
+    pub kind: NoteKind,
 }
 
-const MAX_NOTE_SIZE: usize = 8 * 1024;
-const MAX_TEXT_LEN: usize = 8 * 1000;
+// What the body blob holds
+// Plain is markdown , replaced wholesale (last writer wins)
+// Crdt is a character crdt state , edits from different authors merge.
+// Placeholder is never stored , it stands in for a record we can't read
+// (a newer version wrote it) and is read only.
+// It still serializes so the cli and the api can show it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum NoteKind {
+    #[default]
+    Plain,
+    Crdt,
+    #[serde(skip_deserializing)]
+    Placeholder,
+}
 
-impl Note {
-    fn from_bytes(bytes: Bytes) -> anyhow::Result<Self> {
-        let note = serde_json::from_slice(&bytes).context("invalid json")?;
-        Ok(note)
+// The record that lives in the doc entry.
+// The markdown body is stored as a separate blob and referenced by hash
+// so there is no size limit on the text.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoteRecord {
+    pub id: String,
+    pub body: Hash,
+    pub size: u64,
+    pub created: i64,
+    pub updated: i64,
+    pub is_delete: bool,
+    // hash of the record this one replaced, the revision chain.
+    #[serde(default)]
+    pub prev: Option<Hash>,
+    // other authors' versions folded in when a conflict was resolved.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged: Vec<Hash>,
+    #[serde(default)]
+    pub kind: NoteKind,
+    // the note has been moved , this key just points at the new one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<String>,
+    // which encoding wrote this , see SCHEMA_VERSION
+    #[serde(default)]
+    pub schema: u32,
+    // text of an old inline note , it goes into the blob store
+    // on the next write rather than on every read.
+    #[serde(skip)]
+    inline: Option<String>,
+}
+
+// A single step back in the revision chain.
+#[derive(Clone, Debug)]
+pub struct Revision {
+    pub hash: Hash,
+    pub body: Hash,
+    pub size: u64,
+    pub updated: i64,
+    pub is_delete: bool,
+}
+
+// One author's version of a note, for conflict resolution.
+#[derive(Clone, Debug)]
+pub struct NoteVersion {
+    pub author: AuthorId,
+    pub hash: Hash,
+    pub note: Note,
+}
+
+// Record encodings
+//   0  the whole note inline in the json , text and all
+//   1  record with the body in a blob , no version written down
+//   2  versioned record
+// Bump this and add a step to upgrade() when the record changes shape.
+pub const SCHEMA_VERSION: u32 = 2;
+
+// What can be found in a doc entry.
+// Older notes have the text inline in the json,
+// they get migrated to a record on the next save.
+enum StoredNote {
+    Record(NoteRecord),
+    Inline(Note),
+    // newer than us , or just broken
+    Unreadable { id: String, schema: u32 },
+}
+
+impl StoredNote {
+    // Work out what we have and bring it up to date.
+    // Never fails , anything we can't read becomes a placeholder
+    // so one odd entry doesn't take out the whole list.
+    fn parse(id: &str, bytes: &[u8]) -> Self {
+        let unreadable = |schema| StoredNote::Unreadable {
+            id: id.to_string(),
+            schema,
+        };
+        let value: Value = match serde_json::from_slice(bytes) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("unreadable record for {id} , {e}");
+                return unreadable(0);
+            }
+        };
+        let schema = schema_of(&value);
+        if schema > SCHEMA_VERSION {
+            return unreadable(schema);
+        }
+        let parsed = match schema {
+            0 => serde_json::from_value(value).map(StoredNote::Inline),
+            _ => serde_json::from_value(upgrade(value, schema)).map(StoredNote::Record),
+        };
+        match parsed {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!("bad schema {schema} record for {id} , {e}");
+                unreadable(schema)
+            }
+        }
+    }
+}
+
+// What a placeholder shows instead of the body
+fn placeholder_text(schema: u32) -> String {
+    if schema > SCHEMA_VERSION {
+        format!(
+            "*This note was saved by a newer version (schema {schema}), \
+            upgrade to read or edit it.*"
+        )
+    } else {
+        String::from("*This note could not be read.*")
+    }
+}
+
+// Records say which version they are , older ones have to be guessed.
+fn schema_of(value: &Value) -> u32 {
+    match value.get("schema").and_then(Value::as_u64) {
+        Some(schema) => schema.try_into().unwrap_or(u32::MAX),
+        None if value.get("body").is_some() => 1,
+        None => 0,
+    }
+}
+
+// Step an older record up to the current shape one version at a time.
+// ( 0 -> 1 needs the blob store , that happens in record_from_stored )
+fn upgrade(mut value: Value, from: u32) -> Value {
+    // 1 -> 2 , same fields , the version just gets written down
+    if from < 2 {
+        value["schema"] = 2.into();
+    }
+    // later steps go here , oldest first
+    value
+}
+
+// A bounced down collection kept under a notes-<timestamp> tag.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub tag: String,
+    pub hash: Hash,
+    pub time: i64,
+    pub count: usize,
+}
+
+// A hidden note waiting in the trash
+#[derive(Clone, Debug)]
+pub struct Trashed {
+    pub id: String,
+    // when the hide was written , seconds
+    pub hidden_at: i64,
+    pub hidden_by: String,
+}
+
+// tag prefix for snapshots , followed by the doc and the time
+// ( notes-<time> from before doc sets still shows up everywhere )
+const SNAPSHOT_PREFIX: &str = "notes-";
+// tag prefix for old revisions , followed by the doc and the hash.
+// The doc only replicates the newest entry per author , so the records
+// ( and bodies ) behind prev are fetched while a peer has them and kept here.
+const REVISION_PREFIX: &str = "rev-";
+// tag prefix for new attachments , followed by the doc , the time and the hash.
+// Holds a file until a saved note links to it , the gc only sees linked ones.
+const ATTACH_PREFIX: &str = "attach-";
+// an attachment never linked from a note goes after this long , seconds
+const ATTACH_HOLD: i64 = 7 * 24 * 60 * 60;
+
+// Snapshot retention
+// one an hour for a day , one a day for a month , one a month after that.
+const HOUR: i64 = 60 * 60;
+const DAY: i64 = 24 * HOUR;
+const DAILY_FOR: i64 = 30 * DAY;
+
+// Blobs the garbage collector must leave alone.
+// Note bodies are only referenced from inside the records so the store
+// can't see them , the worker fills this from the doc and new blobs are
+// added as they are made. None until the first fill , gc waits for it.
+pub type Protected = Arc<std::sync::Mutex<Option<HashSet<Hash>>>>;
+
+// Ids of the conflicted notes , None until the first full walk.
+type Conflicts = Arc<std::sync::Mutex<Option<BTreeSet<String>>>>;
+
+// the record is only metadata , so it stays small.
+const MAX_RECORD_SIZE: usize = 8 * 1024;
+// don't walk the chain forever.
+const MAX_HISTORY: usize = 500;
+// or follow renames forever.
+const MAX_REDIRECTS: usize = 16;
+
+impl NoteRecord {
+    // read only stand in , there is no body to fetch
+    fn placeholder(id: String, schema: u32) -> Self {
+        Self {
+            id,
+            body: Hash::EMPTY,
+            size: 0,
+            // not zero , that reads as a missing note
+            created: 1,
+            updated: 1,
+            is_delete: false,
+            prev: None,
+            merged: Vec::new(),
+            kind: NoteKind::Placeholder,
+            moved_to: None,
+            schema,
+            inline: None,
+        }
     }
 
     fn as_bytes(&self) -> anyhow::Result<Bytes> {
+        ensure!(self.kind != NoteKind::Placeholder, "{} is read only", self.id);
         let buf = serde_json::to_vec(self)?;
-        ensure!(buf.len() < MAX_NOTE_SIZE, "todo too large");
+        ensure!(buf.len() < MAX_RECORD_SIZE, "note record too large");
         Ok(buf.into())
     }
 
+    fn revision(&self, hash: Hash) -> Revision {
+        Revision {
+            hash,
+            body: self.body,
+            size: self.size,
+            updated: self.updated,
+            is_delete: self.is_delete,
+        }
+    }
+
+    // glue the body text back on.
+    fn to_note(&self, text: String) -> Note {
+        Note {
+            id: self.id.clone(),
+            text,
+            created: self.created,
+            updated: self.updated,
+            is_delete: self.is_delete,
+            kind: self.kind,
+        }
+    }
+}
+
+impl Note {
     pub fn missing_note(id: String) -> Self {
         Self {
             text: String::from(""),
             created: 0,
             updated: 0,
             is_delete: false,
+            kind: NoteKind::Plain,
             id,
         }
     }
 
+    // never been saved (missing_note)
+    pub fn is_missing(&self) -> bool {
+        self.created == 0 && self.updated == 0
+    }
+
+    // stood in for a record we could not read
+    pub fn is_read_only(&self) -> bool {
+        self.kind == NoteKind::Placeholder
+    }
+
     #[allow(dead_code)]
     pub fn bad_note() -> Self {
         Self {
@@ -79,11 +343,158 @@ impl Note {
             created: 0,
             updated: 0,
             is_delete: false,
+            kind: NoteKind::Plain,
             id: String::from("bad_note"),
         }
     }
 }
 
+// Clean up a note or folder path
+// no empty segments , no leading or trailing slashes.
+pub fn normalize_path(path: &str) -> String {
+    path.split('/')
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Folder markers end in a slash
+pub fn is_folder(id: &str) -> bool {
+    id.ends_with('/')
+}
+
+// YAML front matter for exported notes
+fn front_matter(note: &Note, author: &AuthorId) -> String {
+    let stamp = |t: i64| {
+        DateTime::from_timestamp(t, 0)
+            .map(|d| d.to_rfc3339())
+            .unwrap_or_default()
+    };
+    // a json string is a valid yaml string , saves escaping by hand
+    let id = serde_json::to_string(&note.id).unwrap_or_default();
+    // only snapshots have hidden notes in them
+    let hidden = match note.is_delete {
+        true => "hidden: true\n",
+        false => "",
+    };
+    format!(
+        "---\nid: {}\ncreated: {}\nupdated: {}\nauthor: {}\n{}---\n\n",
+        id,
+        stamp(note.created),
+        stamp(note.updated),
+        author,
+        hidden
+    )
+}
+
+// Which retention slot a snapshot falls in , the newest in each slot is kept.
+fn retention_bucket(time: i64, now: i64) -> (u8, i64) {
+    let age = now - time;
+    if age < DAY {
+        (0, time / HOUR)
+    } else if age < DAILY_FOR {
+        (1, time / DAY)
+    } else {
+        match DateTime::from_timestamp(time, 0) {
+            Some(d) => (2, d.year() as i64 * 12 + d.month() as i64),
+            None => (2, 0),
+        }
+    }
+}
+
+// Turn a snapshot file back into a note.
+// Older snapshots were notes/<date>/<id>.md with no front matter.
+fn note_from_export(name: &str, text: &str) -> Note {
+    let name = name.strip_suffix(".md").unwrap_or(name);
+    let id = match name.strip_prefix("notes/") {
+        // notes/yyyy/mm/dd/
+        Some(dated) => dated.splitn(4, '/').nth(3).unwrap_or(dated),
+        None => name,
+    };
+    let mut note = Note::missing_note(id.to_string());
+    note.text = text.to_string();
+    let Some((pairs, body)) = split_front_matter(text) else {
+        return note;
+    };
+    for (key, value) in pairs {
+        match key.as_str() {
+            "created" => note.created = parse_timestamp(&value).unwrap_or(0),
+            "updated" => note.updated = parse_timestamp(&value).unwrap_or(0),
+            "hidden" => note.is_delete = value == "true",
+            _ => {}
+        }
+    }
+    note.text = body.to_string();
+    note
+}
+
+// Split YAML front matter off the top of a markdown file.
+// Only flat key: value pairs are picked out , None if there is none.
+// Lines that are not simple pairs come back with an empty key.
+pub fn split_front_matter(text: &str) -> Option<(Vec<(String, String)>, &str)> {
+    let mut lines = text.split_inclusive('\n');
+    let first = lines.next()?;
+    if first.trim_end() != "---" {
+        return None;
+    }
+    let mut pairs = Vec::new();
+    let mut pos = first.len();
+    for line in lines {
+        pos += line.len();
+        let line = line.trim_end();
+        if line == "---" || line == "..." {
+            let body = &text[pos..];
+            let body = body
+                .strip_prefix("\r\n")
+                .or(body.strip_prefix('\n'))
+                .unwrap_or(body);
+            return Some((pairs, body));
+        }
+        match line.split_once(':') {
+            Some((key, value)) if !key.starts_with([' ', '-', '#']) => {
+                pairs.push((key.trim().to_string(), unquote(value.trim())));
+            }
+            // nested or list values , keep the line so the caller can tell
+            _ => pairs.push((String::new(), line.to_string())),
+        }
+    }
+    None
+}
+
+// Strip yaml quotes , double quoted is close enough to json
+fn unquote(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        if let Ok(value) = serde_json::from_str::<String>(value) {
+            return value;
+        }
+    }
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return value[1..value.len() - 1].replace("''", "'");
+    }
+    value.to_string()
+}
+
+// Dates as people write them in front matter , unix seconds out.
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Some(seconds);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.timestamp());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(date.and_utc().timestamp());
+        }
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|d| d.and_utc().timestamp())
+}
+
 // Notes outer
 #[derive(Debug, Clone)]
 pub struct Notes(Arc<Inner>);
@@ -92,9 +503,13 @@ pub struct Notes(Arc<Inner>);
 #[derive(Debug, Clone)]
 pub struct Inner {
     blobs: BlobsProtocol,
+    downloader: Downloader,
     doc: Doc,
-    ticket: DocTicket,
+    // joined with a read ticket , can look but not write
+    writable: bool,
     author: AuthorId,
+    protected: Protected,
+    conflicts: Conflicts,
 }
 
 impl Notes {
@@ -104,24 +519,29 @@ impl Notes {
         author: AuthorId,
         blobs: BlobsProtocol,
         docs: Docs,
+        endpoint: &Endpoint,
+        protected: Protected,
     ) -> Result<Self> {
         let author = author;
-        let doc = match ticket {
+        let (doc, writable) = match ticket {
             Some(ticket) => {
                 let ticket = DocTicket::from_str(&ticket)?;
-                docs.import(ticket).await?
+                let writable = matches!(ticket.capability, Capability::Write(_));
+                (docs.import(ticket).await?, writable)
             }
-            None => docs.create().await?,
+            None => (docs.create().await?, true),
         };
-        let ticket = doc
-            .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
-            .await?;
 
+        // note bodies are fetched on demand from the sync peers
+        let downloader = blobs.downloader(endpoint);
         Ok(Self(Arc::new(Inner {
             blobs,
+            downloader,
             doc,
-            ticket,
+            writable,
             author,
+            protected,
+            conflicts: Default::default(),
         })))
     }
 
@@ -131,21 +551,36 @@ impl Notes {
         author: AuthorId,
         blobs: BlobsProtocol,
         docs: Docs,
+        endpoint: &Endpoint,
+        protected: Protected,
     ) -> Result<Self> {
         let doc = docs.open(id).await?;
         let doc = match doc {
             Some(doc) => doc,
             None => return Err(anyhow!("Doc does not exist")),
         };
-        let ticket = doc
-            .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
-            .await?;
+        // the store knows what we were given
+        let mut writable = false;
+        let listed = docs.list().await?;
+        tokio::pin!(listed);
+        while let Some(item) = listed.next().await {
+            let (listed_id, kind) = item?;
+            if listed_id == id {
+                writable = kind == CapabilityKind::Write;
+                break;
+            }
+        }
         let author = author;
+        // note bodies are fetched on demand from the sync peers
+        let downloader = blobs.downloader(endpoint);
         Ok(Self(Arc::new(Inner {
             blobs,
+            downloader,
             doc,
-            ticket,
+            writable,
             author,
+            protected,
+            conflicts: Default::default(),
         })))
     }
 
@@ -154,9 +589,28 @@ impl Notes {
         self.0.doc.id().to_bytes()
     }
 
-    // this is a write ticket for now .
-    pub fn ticket(&self) -> String {
-        self.0.ticket.to_string()
+    // The NamespaceId as it goes in the config
+    pub fn doc_key(&self) -> String {
+        self.0.doc.id().to_string()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.0.writable
+    }
+
+    // A share ticket , read only docs can only hand out read tickets.
+    pub async fn ticket(&self, write: bool) -> Result<String> {
+        ensure!(
+            self.0.writable || !write,
+            "this doc is read only , it can only share read tickets"
+        );
+        let mode = if write { ShareMode::Write } else { ShareMode::Read };
+        let ticket = self
+            .0
+            .doc
+            .share(mode, AddrInfoOptions::RelayAndAddresses)
+            .await?;
+        Ok(ticket.to_string())
     }
 
     // this needs more nuance , for reconnection
@@ -164,19 +618,6 @@ impl Notes {
         self.0.doc.subscribe().await
     }
 
-    // Are we attached ?
-    pub async fn attached(&self) -> bool {
-        match self.0.doc.status().await {
-            Ok(status) => {
-                return status.sync;
-            }
-            Err(e) => {
-                warn!("{:#?}", e);
-                return false;
-            }
-        };
-    }
-
     //  TODO , option friends ?
     pub async fn share(&self,peers: Vec<NodeAddr>) -> Result<()> {
         self.0.doc.start_sync(peers).await?;
@@ -184,43 +625,80 @@ impl Notes {
     }
 
     pub async fn create(&self, id: String, text: String) -> Result<()> {
-        if text.len() > MAX_TEXT_LEN {
-            bail!("text is too long, max size is {MAX_TEXT_LEN}");
-        };
         let created = Utc::now().timestamp();
-        let note = Note {
+        let (body, size) = self.put_body(text).await?;
+        let record = NoteRecord {
             id: id.clone(),
-            text,
+            body,
+            size,
             created,
             updated: created,
             is_delete: false,
+            prev: None,
+            merged: Vec::new(),
+            kind: NoteKind::Plain,
+            moved_to: None,
+            schema: SCHEMA_VERSION,
+            inline: None,
         };
-        self.insert_bytes(id.as_bytes(), note.as_bytes()?).await
+        self.insert_bytes(id.as_bytes(), record.as_bytes()?).await
     }
 
     // Get a list of the notes that exists.
+    // a body we can't get (yet) shows as a missing note , not a failed list.
+    #[allow(dead_code)]
     pub async fn get_notes(&self) -> Result<Vec<Note>> {
-        let entries = self.0.doc.get_many(Query::single_latest_per_key()).await?;
+        let records = self.get_records().await?;
         let mut notes = Vec::new();
+        for record in records {
+            match self.record_text(&record).await {
+                Ok(text) => notes.push(record.to_note(text)),
+                Err(e) => {
+                    warn!("no body for {} , {e}", record.id);
+                    notes.push(Note::missing_note(record.id));
+                }
+            }
+        }
+        Ok(notes)
+    }
+
+    // Just the records, no bodies, newest first.
+    pub async fn get_records(&self) -> Result<Vec<NoteRecord>> {
+        let entries = self.0.doc.get_many(Query::single_latest_per_key()).await?;
+        let mut records = Vec::new();
         tokio::pin!(entries);
         while let Some(entry) = entries.next().await {
             let entry = entry?;
-            let note = self.note_from_entry(&entry).await?;
-            if !note.is_delete {
-                notes.push(note)
+            let record = self.record_from_entry(&entry).await?;
+            // moved notes leave a redirect behind , skip those.
+            if !record.is_delete && record.moved_to.is_none() {
+                records.push(record)
             }
-            // notes.push(note);
         }
-        // println!("{:#?}",notes);
-        notes.sort_by_key(|n| Reverse(n.updated));
-        Ok(notes)
+        records.sort_by_key(|n| Reverse(n.updated));
+        Ok(records)
+    }
+
+    // Every redirect left by a rename , old id to new id
+    pub async fn get_redirects(&self) -> Result<Vec<(String, String)>> {
+        let entries = self.0.doc.get_many(Query::single_latest_per_key()).await?;
+        let mut redirects = Vec::new();
+        tokio::pin!(entries);
+        while let Some(entry) = entries.next().await {
+            let record = self.record_from_entry(&entry?).await?;
+            if let Some(to) = record.moved_to {
+                redirects.push((record.id, to));
+            }
+        }
+        Ok(redirects)
     }
 
     // Just get a vec of the notes for the left hand side menu.
+    // only needs the records, the bodies can stay where they are.
     pub async fn get_note_vec(&self) -> Vec<String> {
-        let note_list_res = self.get_notes().await;
+        let note_list_res = self.get_records().await;
         let items = match note_list_res {
-            Ok(notes) => notes.iter().map(|n| n.id.clone()).collect(),
+            Ok(records) => records.iter().map(|n| n.id.clone()).collect(),
             Err(e) => vec![format!("{e}")],
         };
         items
@@ -228,17 +706,20 @@ impl Notes {
 
     //Grab the actual note
     pub async fn get_note(&self, id: String) -> Result<Note> {
-        let mut ex_key = id.as_bytes().to_vec();
-        ex_key.push(0);
-        let entry_option = self
-            .0
-            .doc
-            .get_one(Query::single_latest_per_key().key_exact(&ex_key))
-            .await?;
-        match entry_option {
-            Some(entry) => {
-                // println!("{:#?}", entry);
-                self.note_from_entry(&entry).await
+        // old names point at the new one
+        let id = self.resolve_id(id).await?;
+        match self.get_record(&id).await? {
+            // crdt notes merge every author's state on the way out
+            Some(record) if record.kind == NoteKind::Crdt => {
+                let text = match self.merged_crdt(&id).await? {
+                    Some(crdt) => crdt.text(),
+                    None => self.record_text(&record).await?,
+                };
+                Ok(record.to_note(text))
+            }
+            Some(record) => {
+                let text = self.record_text(&record).await?;
+                Ok(record.to_note(text))
             }
             None => Ok(Note::missing_note(id.clone())),
         }
@@ -246,22 +727,203 @@ impl Notes {
 
     // Note has changed check and save.
     pub async fn update_note(&self, id: String, text: String) -> Result<()> {
-        if text.len() > MAX_TEXT_LEN {
-            bail!("text is too long, max size is {MAX_TEXT_LEN}");
+        self.update_note_merged(id, text, Vec::new()).await
+    }
+
+    // Save, folding in other heads if this is a conflict resolution.
+    async fn update_note_merged(&self, id: String, text: String, merged: Vec<Hash>) -> Result<()> {
+        let id = self.resolve_id(id).await?;
+        let now = Utc::now().timestamp();
+        let record_res = self.get_record(&id).await;
+        let record = match record_res {
+            // writing a new record also migrates inline notes.
+            Ok(Some(mut record)) => {
+                let (body, size) = match record.kind {
+                    NoteKind::Plain => self.put_body(text).await?,
+                    NoteKind::Crdt => self.put_crdt_edit(&id, &text).await?,
+                    NoteKind::Placeholder => bail!("{id} is read only"),
+                };
+                record.body = body;
+                record.size = size;
+                record.updated = now;
+                record.merged = merged;
+                record
+            }
+            _ => {
+                let (body, size) = self.put_body(text).await?;
+                NoteRecord {
+                    id: id.clone(),
+                    body,
+                    size,
+                    created: now,
+                    updated: now,
+                    is_delete: false,
+                    prev: None,
+                    merged,
+                    kind: NoteKind::Plain,
+                    moved_to: None,
+                    schema: SCHEMA_VERSION,
+                    inline: None,
+                }
+            }
         };
-        let note_res = self.get_note(id.clone()).await;
-        let mut note = match note_res {
-            Ok(note) => note,
-            Err(_) => Note::missing_note("missing".to_string()),
+        warn!("note prewrite id {:#?} , {:#?}", &id.as_bytes(), &record);
+        self.update_bytes(id.as_bytes(), record).await
+    }
+
+    // Conflicts
+    // single_latest_per_key hides the other authors,
+    // so look at every author's entry for the key.
+
+    // All the versions if the authors have diverged , None if they agree.
+    pub async fn get_versions(&self, id: String) -> Result<Option<Vec<NoteVersion>>> {
+        let entries = self.get_author_entries(id.as_bytes()).await?;
+        if !self.is_conflicted(&id, &entries).await? {
+            return Ok(None);
+        }
+        let mut versions = Vec::new();
+        for entry in entries {
+            let record = self.record_from_entry(&entry).await?;
+            let text = self.record_text(&record).await?;
+            versions.push(NoteVersion {
+                author: entry.author(),
+                hash: entry.content_hash(),
+                note: record.to_note(text),
+            });
+        }
+        Ok(Some(versions))
+    }
+
+    // Ids of every note with diverged versions.
+    // The whole doc is only walked once , after that check_conflict
+    // keeps the set up to date a note at a time.
+    // Missed changes , the next get_conflicts walks everything again
+    pub fn forget_conflicts(&self) {
+        if let Ok(mut conflicts) = self.0.conflicts.lock() {
+            *conflicts = None;
+        }
+    }
+
+    pub async fn get_conflicts(&self) -> Result<Vec<String>> {
+        if let Some(known) = self.known_conflicts() {
+            return Ok(known);
+        }
+        let found = self.find_conflicts().await?;
+        if let Ok(mut conflicts) = self.0.conflicts.lock() {
+            *conflicts = Some(found.iter().cloned().collect());
+        }
+        Ok(found)
+    }
+
+    fn known_conflicts(&self) -> Option<Vec<String>> {
+        let conflicts = self.0.conflicts.lock().ok()?;
+        conflicts.as_ref().map(|set| set.iter().cloned().collect())
+    }
+
+    // A note changed , true if that changed whether it is conflicted.
+    pub async fn check_conflict(&self, id: &str) -> Result<bool> {
+        // not worked out yet , the first get_conflicts will see it
+        if self.known_conflicts().is_none() {
+            return Ok(false);
+        }
+        let entries = self.get_author_entries(id.as_bytes()).await?;
+        let conflicted = entries.len() > 1 && self.is_conflicted(id, &entries).await?;
+        let Ok(mut conflicts) = self.0.conflicts.lock() else {
+            return Ok(false);
+        };
+        let Some(set) = conflicts.as_mut() else {
+            return Ok(false);
+        };
+        Ok(match conflicted {
+            true => set.insert(id.to_string()),
+            false => set.remove(id),
+        })
+    }
+
+    async fn find_conflicts(&self) -> Result<Vec<String>> {
+        let entries = self.0.doc.get_many(Query::all()).await?;
+        let mut keys: BTreeMap<Vec<u8>, Vec<Entry>> = BTreeMap::new();
+        tokio::pin!(entries);
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            keys.entry(entry.key().to_owned()).or_default().push(entry);
+        }
+        let mut conflicts = Vec::new();
+        for (key, mut entries) in keys {
+            // one author , nothing to argue about.
+            if entries.len() < 2 {
+                continue;
+            }
+            entries.sort_by_key(|e| Reverse(e.timestamp()));
+            let key = key.strip_suffix(&[0]).unwrap_or(&key);
+            let id = String::from_utf8(key.to_owned()).context("invalid key")?;
+            if self.is_conflicted(&id, &entries).await? {
+                conflicts.push(id);
+            }
+        }
+        Ok(conflicts)
+    }
+
+    // Take the merged text as the new version ,
+    // the other heads go into the record so they are ancestors.
+    pub async fn resolve_conflict(&self, id: String, text: String, heads: Vec<Hash>) -> Result<()> {
+        self.update_note_merged(id, text, heads).await
+    }
+
+    // Every author's latest entry for a key, newest first.
+    async fn get_author_entries(&self, key: &[u8]) -> Result<Vec<Entry>> {
+        let mut ex_key = key.to_vec();
+        ex_key.push(0);
+        let entries = self.0.doc.get_many(Query::key_exact(&ex_key)).await?;
+        let mut found = Vec::new();
+        tokio::pin!(entries);
+        while let Some(entry) = entries.next().await {
+            found.push(entry?);
+        }
+        found.sort_by_key(|e| Reverse(e.timestamp()));
+        Ok(found)
+    }
+
+    // Diverged if some author's head is not an ancestor of the newest
+    // and the newest is not an ancestor of it either.
+    async fn is_conflicted(&self, id: &str, entries: &[Entry]) -> Result<bool> {
+        let Some((newest, rest)) = entries.split_first() else {
+            return Ok(false);
         };
-        note.text = text;
-        note.updated = Utc::now().timestamp();
-        warn!("note prewrite id {:#?} , {:#?}", &id.as_bytes(), &note);
-        let res = self.update_bytes(id.as_bytes(), note).await;
-        match res {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+        // crdt notes merge by themselves.
+        if self.record_from_entry(newest).await?.kind == NoteKind::Crdt {
+            return Ok(false);
         }
+        let ancestors = self.ancestry(newest.content_hash(), id).await;
+        for entry in rest {
+            if ancestors.contains(&entry.content_hash()) {
+                continue;
+            }
+            let theirs = self.ancestry(entry.content_hash(), id).await;
+            if !theirs.contains(&newest.content_hash()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Every record hash reachable from this one (including itself).
+    async fn ancestry(&self, hash: Hash, id: &str) -> HashSet<Hash> {
+        let mut seen = HashSet::new();
+        let mut todo = vec![hash];
+        while let Some(hash) = todo.pop() {
+            if seen.len() >= MAX_HISTORY || !seen.insert(hash) {
+                continue;
+            }
+            match self.record_from_hash(hash, id).await {
+                Ok(record) => {
+                    todo.extend(record.prev);
+                    todo.extend(record.merged);
+                }
+                Err(e) => warn!("ancestry stopped at {} , {e}", hash.fmt_short()),
+            }
+        }
+        seen
     }
 
     // Mark hidden for later deletion.
@@ -273,32 +935,493 @@ impl Notes {
         Ok(())
     }
 
-    // Delete hidden notes , this should bounce down first
+    // Delete hidden notes , the worker snapshots first
     // for backup.
     pub async fn delete_hidden(&self) -> Result<()> {
+        for trashed in self.trash().await? {
+            let others = self.delete_forever(trashed.id.clone()).await?;
+            if others > 0 {
+                warn!("{} is still held by {others} other authors", trashed.id);
+            }
+        }
+        Ok(())
+    }
+
+    // Hidden notes , most recently hidden first.
+    // The latest entry is the hide , so its time and author say when and who.
+    pub async fn trash(&self) -> Result<Vec<Trashed>> {
         let entries = self.0.doc.get_many(Query::single_latest_per_key()).await?;
         tokio::pin!(entries);
+        let mut trash = Vec::new();
         while let Some(entry) = entries.next().await {
             let entry = entry?;
-            let note = self.note_from_entry(&entry).await?;
-            if note.is_delete {
-                // println!("{:#?}", note);
-                let _val = self
-                    .0
-                    .doc
-                    .del(self.0.author, entry.key().to_owned())
-                    .await?;
-                // println!("{:?} nodes deleted", val);
+            let record = self.record_from_entry(&entry).await?;
+            if !record.is_delete {
+                continue;
             }
+            let author = entry.author();
+            let hidden_by = if author == self.0.author {
+                String::from("you")
+            } else {
+                author.fmt_short().to_string()
+            };
+            trash.push(Trashed {
+                id: record.id,
+                hidden_at: (entry.timestamp() / 1_000_000) as i64,
+                hidden_by,
+            });
         }
-        Ok(())
+        trash.sort_by_key(|t| Reverse(t.hidden_at));
+        Ok(trash)
+    }
+
+    // Ids of notes that have been in the trash longer than days
+    pub async fn expired_trash(&self, days: u64) -> Result<Vec<String>> {
+        let cutoff = Utc::now().timestamp() - days as i64 * DAY;
+        Ok(self
+            .trash()
+            .await?
+            .into_iter()
+            .filter(|t| t.hidden_at < cutoff)
+            .map(|t| t.id)
+            .collect())
     }
 
-    // Set hidden for later deletion.
+    // Take a note back out of the trash
+    pub async fn restore_note(&self, id: String) -> Result<()> {
+        match self.get_record(&id).await? {
+            Some(record) => ensure!(record.is_delete, "{id} is not in the trash"),
+            None => bail!("no note called {id}"),
+        }
+        self.set_hidden(id, false).await
+    }
+
+    // Actually delete a hidden note , only snapshots still have it.
+    // We can only remove our own author's entries , other people's copies stay.
+    // Those would bring an old version back , so an empty hidden record
+    // goes on top and keeps it in the trash until they delete theirs.
+    // Returns how many other authors still hold a copy.
+    pub async fn delete_forever(&self, id: String) -> Result<usize> {
+        let record = match self.get_record(&id).await? {
+            Some(record) => record,
+            None => bail!("no note called {id}"),
+        };
+        ensure!(record.is_delete, "hide {id} before deleting it");
+        // the exact key , del works on prefixes
+        let mut key = id.clone().into_bytes();
+        key.push(0);
+        self.0.doc.del(self.0.author, key.clone()).await?;
+
+        let entries = self.0.doc.get_many(Query::all().key_exact(&key)).await?;
+        tokio::pin!(entries);
+        let mut others = 0;
+        while let Some(entry) = entries.next().await {
+            if entry?.author() != self.0.author {
+                others += 1;
+            }
+        }
+        if others > 0 {
+            let (body, size) = self.put_body(String::new()).await?;
+            let hidden = NoteRecord {
+                body,
+                size,
+                updated: Utc::now().timestamp(),
+                is_delete: true,
+                prev: None,
+                merged: Vec::new(),
+                kind: NoteKind::Plain,
+                inline: None,
+                ..record
+            };
+            self.update_bytes(id.as_bytes(), hidden).await?;
+        }
+        Ok(others)
+    }
+
+    // Set hidden for later deletion , a toggle.
     pub async fn set_delete(&self, id: String) -> Result<()> {
-        let mut note = self.get_note(id.clone()).await?;
-        note.is_delete = !note.is_delete;
-        self.update_bytes(id.clone(), note).await
+        let id = self.resolve_id(id).await?;
+        let hidden = match self.get_record(&id).await? {
+            Some(record) => record.is_delete,
+            None => bail!("no note called {id}"),
+        };
+        self.set_hidden(id, !hidden).await
+    }
+
+    // Hide or show , nothing is written if it is that way already.
+    pub async fn set_hidden(&self, id: String, hidden: bool) -> Result<()> {
+        let id = self.resolve_id(id).await?;
+        let mut record = match self.get_record(&id).await? {
+            Some(record) => record,
+            None => bail!("no note called {id}"),
+        };
+        if record.is_delete == hidden {
+            return Ok(());
+        }
+        record.is_delete = hidden;
+        record.merged = Vec::new();
+        self.update_bytes(id.clone(), record).await
+    }
+
+    // Folders
+    // folders are just path prefixes on the note ids ( projects/liminal/roadmap )
+    // an empty folder is kept alive by a marker note ending in a slash.
+
+    // Make an empty folder
+    pub async fn create_folder(&self, path: String) -> Result<()> {
+        let path = normalize_path(&path);
+        ensure!(!path.is_empty(), "folder name is empty");
+        let marker = format!("{path}/");
+        if self.exists(&marker).await? {
+            bail!("folder {path} already exists");
+        }
+        self.create(marker, String::new()).await
+    }
+
+    // Move (or rename) a folder , every note under it gets re-keyed.
+    pub async fn move_folder(&self, from: String, to: String) -> Result<usize> {
+        let from = normalize_path(&from);
+        let to = normalize_path(&to);
+        ensure!(!from.is_empty() && !to.is_empty(), "folder name is empty");
+        ensure!(from != to, "folder is already called {to}");
+        let from_prefix = format!("{from}/");
+        let to_prefix = format!("{to}/");
+        ensure!(
+            !to_prefix.starts_with(&from_prefix),
+            "can't move a folder inside itself"
+        );
+        let moving: Vec<String> = self
+            .get_records()
+            .await?
+            .into_iter()
+            .map(|r| r.id)
+            .filter(|id| id.starts_with(&from_prefix))
+            .collect();
+        ensure!(!moving.is_empty(), "no folder called {from}");
+        // check everything first so nothing gets half moved
+        let mut pairs = Vec::new();
+        for id in moving {
+            let new_id = format!("{to_prefix}{}", &id[from_prefix.len()..]);
+            if self.exists(&new_id).await? {
+                bail!("{new_id} already exists");
+            }
+            pairs.push((id, new_id));
+        }
+        for (id, new_id) in pairs.iter() {
+            self.rekey(id, new_id).await?;
+        }
+        Ok(pairs.len())
+    }
+
+    // Renames
+    // the record moves to the new key with its created time and revisions,
+    // the old key is left as a redirect.
+
+    pub async fn rename_note(&self, from: String, to: String) -> Result<String> {
+        let from = normalize_path(&from);
+        let to = normalize_path(&to);
+        ensure!(!to.is_empty(), "new name is empty");
+        ensure!(from != to, "note is already called {to}");
+        ensure!(!is_folder(&from), "{from} is a folder");
+        if !self.exists(&from).await? {
+            bail!("no note called {from}");
+        }
+        // never overwrite
+        if self.exists(&to).await? {
+            bail!("{to} already exists");
+        }
+        self.rekey(&from, &to).await?;
+        Ok(to)
+    }
+
+    // Follow redirects to where the note lives now
+    async fn resolve_id(&self, id: String) -> Result<String> {
+        let mut id = id;
+        // don't chase loops forever
+        for _ in 0..MAX_REDIRECTS {
+            match self.get_record(&id).await? {
+                Some(NoteRecord {
+                    moved_to: Some(to),
+                    ..
+                }) => id = to,
+                _ => return Ok(id),
+            }
+        }
+        bail!("too many redirects for {id}")
+    }
+
+    // Is there a live note at this id
+    async fn exists(&self, id: &str) -> Result<bool> {
+        match self.get_record(id).await? {
+            Some(record) => Ok(record.moved_to.is_none()),
+            None => Ok(false),
+        }
+    }
+
+    // Move a note to a new key.
+    // The new key carries on the revision chain,
+    // the old key gets a redirect so other authors don't bring it back.
+    async fn rekey(&self, from: &str, to: &str) -> Result<()> {
+        let Some(entry) = self.get_entry(from.as_bytes()).await? else {
+            bail!("no note called {from}");
+        };
+        let mut record = self.record_from_entry(&entry).await?;
+        // both copies point at the body , it has to be in the store
+        self.store_inline(&mut record).await?;
+        let mut moved = record.clone();
+        moved.id = to.to_string();
+        moved.prev = Some(entry.content_hash());
+        moved.merged = Vec::new();
+        // crdt notes take every author's edits with them
+        if record.kind == NoteKind::Crdt {
+            if let Some(crdt) = self.merged_crdt(from).await? {
+                moved.body = self.add_blob(crdt.as_bytes()?).await?;
+            }
+        }
+        self.insert_bytes(to.as_bytes(), moved.as_bytes()?).await?;
+        let mut redirect = record;
+        redirect.moved_to = Some(to.to_string());
+        redirect.merged = Vec::new();
+        redirect.updated = Utc::now().timestamp();
+        self.update_bytes(from.as_bytes(), redirect).await
+    }
+
+    // Walk back through the revision chain, newest first.
+    // renamed notes carry on through the old key's records.
+    pub async fn history(&self, id: String) -> Result<Vec<Revision>> {
+        let id = self.resolve_id(id).await?;
+        let mut revisions = Vec::new();
+        let mut next = self
+            .get_entry(id.as_bytes())
+            .await?
+            .map(|entry| entry.content_hash());
+        while let Some(hash) = next {
+            if revisions.len() >= MAX_HISTORY {
+                break;
+            }
+            let record = match self.record_from_hash(hash, &id).await {
+                Ok(record) => record,
+                Err(e) => {
+                    // older peers may have dropped the record , stop here.
+                    warn!("revision chain broken at {} , {e}", hash.fmt_short());
+                    break;
+                }
+            };
+            next = record.prev;
+            revisions.push(record.revision(hash));
+        }
+        Ok(revisions)
+    }
+
+    // Pull the revision chains behind every author's entry into the store
+    // and tag them , so history works here after the writer goes away.
+    // Stops at revisions already kept , so only new ones cost anything.
+    pub async fn keep_history(&self, id: String) -> Result<()> {
+        let entries = self.get_author_entries(id.as_bytes()).await?;
+        let mut todo: Vec<Hash> = entries.iter().map(|e| e.content_hash()).collect();
+        let mut seen = HashSet::new();
+        while let Some(hash) = todo.pop() {
+            if seen.len() >= MAX_HISTORY || !seen.insert(hash) {
+                continue;
+            }
+            if self.is_kept(hash).await? {
+                continue;
+            }
+            let record = match self.record_from_hash(hash, &id).await {
+                Ok(record) => record,
+                // nobody has it right now , try again on the next change
+                Err(e) => {
+                    warn!("revision {} of {id} not kept , {e}", hash.fmt_short());
+                    continue;
+                }
+            };
+            if record.kind != NoteKind::Placeholder && record.inline.is_none() {
+                match self.get_blob(&record.body).await {
+                    Ok(_) => self.keep(record.body).await?,
+                    Err(e) => warn!("body of {} not kept , {e}", hash.fmt_short()),
+                }
+            }
+            self.keep(hash).await?;
+            todo.extend(record.prev);
+            todo.extend(record.merged);
+        }
+        Ok(())
+    }
+
+    fn revision_tag(&self, hash: Hash) -> String {
+        let id = self.0.doc.id().to_string();
+        format!("{REVISION_PREFIX}{}-{hash}", &id[..10])
+    }
+
+    async fn is_kept(&self, hash: Hash) -> Result<bool> {
+        Ok(self.0.blobs.tags().get(self.revision_tag(hash)).await?.is_some())
+    }
+
+    // Tag a revision blob so the gc leaves it alone
+    async fn keep(&self, hash: Hash) -> Result<()> {
+        self.0
+            .blobs
+            .tags()
+            .set(self.revision_tag(hash), HashAndFormat::raw(hash))
+            .await?;
+        Ok(())
+    }
+
+    // Get an old version of a note.
+    pub async fn get_revision(&self, id: String, hash: Hash) -> Result<Note> {
+        let record = self.record_from_hash(hash, &id).await?;
+        let text = self.record_text(&record).await?;
+        Ok(record.to_note(text))
+    }
+
+    // Put an old version back , this is a new revision
+    // so the history is not rewritten.
+    pub async fn restore_revision(&self, id: String, hash: Hash) -> Result<()> {
+        let note = self.get_revision(id.clone(), hash).await?;
+        self.update_note(id, note.text).await
+    }
+
+    // Attachments
+    // files live in the blob store , the note only holds the hash.
+
+    // the store streams the file in , big files never sit in memory
+    pub async fn add_attachment(&self, path: &Path) -> Result<Hash> {
+        // the store wants an absolute path
+        let path = tokio::fs::canonicalize(path)
+            .await
+            .with_context(|| format!("can't read {}", path.display()))?;
+        let hash = self
+            .0
+            .blobs
+            .add_path(&path)
+            .await
+            .with_context(|| format!("can't add {}", path.display()))?
+            .hash;
+        self.protect(hash);
+        let tag = format!("{}{}-{hash}", self.attach_prefix(), Utc::now().timestamp());
+        self.0
+            .blobs
+            .tags()
+            .set(tag, HashAndFormat::raw(hash))
+            .await?;
+        Ok(hash)
+    }
+
+    fn attach_prefix(&self) -> String {
+        let id = self.0.doc.id().to_string();
+        format!("{ATTACH_PREFIX}{}-", &id[..10])
+    }
+
+    // Drop the hold on attachments a note links to now ( or never will ).
+    // Returns how many were let go.
+    pub async fn release_attachments(&self, live: &HashSet<Hash>) -> Result<usize> {
+        let prefix = self.attach_prefix();
+        let now = Utc::now().timestamp();
+        let mut done = Vec::new();
+        let mut tags = self.0.blobs.tags().list_prefix(prefix.as_str()).await?;
+        while let Some(tag) = tags.next().await {
+            let tag = tag?;
+            let name = String::from_utf8_lossy(&tag.name.0).to_string();
+            let time: i64 = name[prefix.len()..]
+                .split('-')
+                .next()
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(0);
+            if live.contains(&tag.hash) || now - time > ATTACH_HOLD {
+                done.push(name);
+            }
+        }
+        for name in done.iter() {
+            self.0.blobs.tags().delete(name.as_str()).await?;
+        }
+        Ok(done.len())
+    }
+
+    // the bytes , fetched from the sync peers if they are not here
+    pub async fn get_attachment(&self, hash: Hash) -> Result<Bytes> {
+        self.get_blob(&hash).await
+    }
+
+    // Any blob , for the export files
+    pub async fn blob(&self, hash: Hash) -> Result<Bytes> {
+        self.get_blob(&hash).await
+    }
+
+    // Collaborative notes
+
+    // Turn a plain note into a crdt note , the text becomes the first state.
+    pub async fn convert_to_crdt(&self, id: String) -> Result<()> {
+        let mut record = match self.get_record(&id).await? {
+            Some(record) => record,
+            None => bail!("no note called {id}"),
+        };
+        ensure!(record.kind == NoteKind::Plain, "{id} is already collaborative");
+        let text = self.record_text(&record).await?;
+        let crdt = CrdtText::from_text(&text, site_id(&self.0.author));
+        record.body = self.add_blob(crdt.as_bytes()?).await?;
+        record.size = text.len() as u64;
+        record.kind = NoteKind::Crdt;
+        record.merged = Vec::new();
+        self.update_bytes(id.as_bytes(), record).await
+    }
+
+    // A remote entry arrived , fold it into our own state.
+    // Only written if we already have an entry and it changed
+    // so peers stop once they agree.
+    pub async fn merge_remote(&self, id: String) -> Result<bool> {
+        let Some(mut record) = self.get_record(&id).await? else {
+            return Ok(false);
+        };
+        if record.kind != NoteKind::Crdt {
+            return Ok(false);
+        }
+        let mut ex_key = id.as_bytes().to_vec();
+        ex_key.push(0);
+        let Some(own) = self.0.doc.get_exact(self.0.author, ex_key, false).await? else {
+            return Ok(false);
+        };
+        let own = self.record_from_entry(&own).await?;
+        if own.kind != NoteKind::Crdt {
+            return Ok(false);
+        }
+        let Some(merged) = self.merged_crdt(&id).await? else {
+            return Ok(false);
+        };
+        if merged == self.get_crdt(&own.body).await? {
+            return Ok(false);
+        }
+        let text = merged.text();
+        record.body = self.add_blob(merged.as_bytes()?).await?;
+        record.size = text.len() as u64;
+        record.merged = Vec::new();
+        self.update_bytes(id.as_bytes(), record).await?;
+        Ok(true)
+    }
+
+    // Every author's crdt state for a note merged together.
+    async fn merged_crdt(&self, id: &str) -> Result<Option<CrdtText>> {
+        let entries = self.get_author_entries(id.as_bytes()).await?;
+        let mut merged: Option<CrdtText> = None;
+        for entry in entries {
+            let record = self.record_from_entry(&entry).await?;
+            if record.kind != NoteKind::Crdt {
+                continue;
+            }
+            let crdt = self.get_crdt(&record.body).await?;
+            match &mut merged {
+                Some(merged) => merged.merge(&crdt),
+                None => merged = Some(crdt),
+            }
+        }
+        Ok(merged)
+    }
+
+    // Apply a text edit to the merged state and store it.
+    async fn put_crdt_edit(&self, id: &str, text: &str) -> Result<(Hash, u64)> {
+        let mut crdt = self.merged_crdt(id).await?.unwrap_or_default();
+        crdt.edit(text, site_id(&self.0.author));
+        let hash = self.add_blob(crdt.as_bytes()?).await?;
+        Ok((hash, text.len() as u64))
     }
 
     // Doc data manipulation , low level data work
@@ -308,78 +1431,526 @@ impl Notes {
         // null byte exend the key
         let mut ex_key = key.as_ref().to_vec();
         ex_key.push(0);
-        self.0.doc.set_bytes(self.0.author, ex_key, value).await?;
+        let hash = self.0.doc.set_bytes(self.0.author, ex_key, value).await?;
+        // superseded records are only held by the prev chain
+        self.protect(hash);
+        self.keep(hash).await
+    }
+
+    // already have the record , update the data.
+    // the current entry becomes the previous revision.
+    async fn update_bytes(&self, key: impl AsRef<[u8]>, mut record: NoteRecord) -> Result<()> {
+        self.store_inline(&mut record).await?;
+        if let Some(entry) = self.get_entry(key.as_ref()).await? {
+            record.prev = Some(entry.content_hash());
+        }
+        let content = record.as_bytes()?;
+        let id = String::from_utf8_lossy(key.as_ref()).to_string();
+        self.insert_bytes(key, content).await?;
+        // a save can settle ( or start ) a conflict
+        self.check_conflict(&id).await?;
+        Ok(())
+    }
+
+    // Find the latest entry for a key
+    async fn get_entry(&self, key: &[u8]) -> Result<Option<Entry>> {
+        let mut ex_key = key.to_vec();
+        ex_key.push(0);
+        let entry = self
+            .0
+            .doc
+            .get_one(Query::single_latest_per_key().key_exact(&ex_key))
+            .await?;
+        Ok(entry)
+    }
+
+    // Find the latest record for a note id
+    async fn get_record(&self, id: &str) -> Result<Option<NoteRecord>> {
+        match self.get_entry(id.as_bytes()).await? {
+            Some(entry) => Ok(Some(self.record_from_entry(&entry).await?)),
+            None => Ok(None),
+        }
+    }
+
+    // get a record from the doc construct.
+    async fn record_from_entry(&self, entry: &Entry) -> Result<NoteRecord> {
+        let key = entry.key().strip_suffix(&[0]).unwrap_or(entry.key());
+        let id = String::from_utf8(key.to_owned()).context("invalid key")?;
+        let stored = match self.0.blobs.get_bytes(entry.content_hash()).await {
+            Ok(b) => StoredNote::parse(&id, &b),
+            // content has not arrived yet.
+            Err(_) => StoredNote::Inline(Note::missing_note(id)),
+        };
+        self.record_from_stored(stored).await
+    }
+
+    // get an older record from the chain, fetching if needed.
+    async fn record_from_hash(&self, hash: Hash, id: &str) -> Result<NoteRecord> {
+        if !self.0.blobs.has(hash).await? {
+            self.fetch_blob(hash).await?;
+        }
+        let bytes = self.0.blobs.get_bytes(hash).await?;
+        self.record_from_stored(StoredNote::parse(id, &bytes)).await
+    }
+
+    // inline notes look like records with the text carried along ,
+    // nothing is written until the note is ( see store_inline ).
+    async fn record_from_stored(&self, stored: StoredNote) -> Result<NoteRecord> {
+        match stored {
+            StoredNote::Record(record) => Ok(record),
+            StoredNote::Inline(note) => {
+                Ok(NoteRecord {
+                    id: note.id,
+                    body: Hash::new(&note.text),
+                    size: note.text.len() as u64,
+                    created: note.created,
+                    updated: note.updated,
+                    is_delete: note.is_delete,
+                    prev: None,
+                    merged: Vec::new(),
+                    kind: note.kind,
+                    moved_to: None,
+                    schema: SCHEMA_VERSION,
+                    inline: Some(note.text),
+                })
+            }
+            StoredNote::Unreadable { id, schema } => Ok(NoteRecord::placeholder(id, schema)),
+        }
+    }
+
+    // Note bodies
+
+    // Everything we add goes through here so the gc knows about it.
+    async fn add_blob(&self, data: impl Into<Bytes>) -> Result<Hash> {
+        let hash = self.0.blobs.add_bytes(data.into()).await?.hash;
+        self.protect(hash);
+        Ok(hash)
+    }
+
+    fn protect(&self, hash: Hash) {
+        if let Ok(mut protected) = self.0.protected.lock() {
+            if let Some(set) = protected.as_mut() {
+                set.insert(hash);
+            }
+        }
+    }
+
+    // The one time migration of an inline note , on its next write.
+    async fn store_inline(&self, record: &mut NoteRecord) -> Result<()> {
+        // an edit replaces the body , then the old text is not needed
+        if let Some(text) = record.inline.take() {
+            if record.body == Hash::new(&text) {
+                self.put_body(text).await?;
+            }
+        }
         Ok(())
     }
 
-    // already have the note , update the data.
-    async fn update_bytes(&self, key: impl AsRef<[u8]>, note: Note) -> Result<()> {
-        let content = note.as_bytes()?;
-        self.insert_bytes(key, content).await
+    // Push the markdown into the blob store.
+    async fn put_body(&self, text: String) -> Result<(Hash, u64)> {
+        let size = text.len() as u64;
+        let hash = self.add_blob(text).await?;
+        Ok((hash, size))
     }
 
-    // get a note from the doc construct.
-    async fn note_from_entry(&self, entry: &Entry) -> Result<Note> {
-        let id = String::from_utf8(entry.key().to_owned()).context("invalid key")?;
-        match self.0.blobs.get_bytes(entry.content_hash()).await {
-            Ok(b) => Note::from_bytes(b),
-            Err(_) => Ok(Note::missing_note(id)),
+    // The markdown for a record , crdt bodies get decoded.
+    async fn record_text(&self, record: &NoteRecord) -> Result<String> {
+        if let Some(text) = &record.inline {
+            return Ok(text.clone());
+        }
+        match record.kind {
+            NoteKind::Plain => self.get_body(&record.body).await,
+            NoteKind::Crdt => Ok(self.get_crdt(&record.body).await?.text()),
+            NoteKind::Placeholder => Ok(placeholder_text(record.schema)),
         }
     }
 
-    // Save out the docs as date stamped .md files
-    // need to add a bit more metadata here.
-    // crete the collection and check if anything has changed;
-    // if not don't save...
-    #[allow(dead_code)]
-    pub async fn bounce_down(&self) -> Result<()> {
+    // Get the markdown out of the blob store.
+    async fn get_body(&self, hash: &Hash) -> Result<String> {
+        let bytes = self.get_blob(hash).await?;
+        let text = String::from_utf8(bytes.to_vec()).context("body is not utf8")?;
+        Ok(text)
+    }
+
+    // Get a crdt state out of the blob store.
+    async fn get_crdt(&self, hash: &Hash) -> Result<CrdtText> {
+        let bytes = self.get_blob(hash).await?;
+        CrdtText::from_bytes(&bytes)
+    }
+
+    // The doc only replicates the records,
+    // so if the body is not here ask the sync peers for it.
+    async fn get_blob(&self, hash: &Hash) -> Result<Bytes> {
+        if !self.0.blobs.has(*hash).await? {
+            self.fetch_blob(*hash).await?;
+        }
+        let bytes = self.0.blobs.get_bytes(*hash).await?;
+        Ok(bytes)
+    }
+
+    // Download a blob from whoever we are syncing with.
+    async fn fetch_blob(&self, hash: Hash) -> Result<()> {
+        let peers: Vec<NodeId> = match self.0.doc.get_sync_peers().await? {
+            Some(peers) => peers
+                .iter()
+                .filter_map(|p| NodeId::from_bytes(p).ok())
+                .collect(),
+            None => Vec::new(),
+        };
+        if peers.is_empty() {
+            bail!("blob {} is not local and there are no peers", hash.fmt_short());
+        }
+        self.0
+            .downloader
+            .download(hash, Shuffled::new(peers))
+            .await
+            .with_context(|| format!("failed to fetch blob {}", hash.fmt_short()))?;
+        self.protect(hash);
+        Ok(())
+    }
+
+    // Bundle every live note up as markdown files with front matter
+    // names are <id>.md and sorted , so the same notes make the same hash
+    // and the caller can tell if anything changed.
+    // Snapshots keep the hidden notes as well , exports don't.
+    pub async fn bounce_down(&self, include_hidden: bool) -> Result<(Hash, Collection)> {
         let entries = self.0.doc.get_many(Query::single_latest_per_key()).await?;
         let mut notes = Vec::new();
         tokio::pin!(entries);
         while let Some(entry) = entries.next().await {
             let entry = entry?;
-            let note = self.note_from_entry(&entry).await?;
-            if !note.is_delete {
-                let h = self.0.blobs.add_bytes(note.text).await?.hash;
-                let mut file_name = Local::now().format("notes/%Y/%m/%d/").to_string();
-                file_name.push_str(&note.id.clone());
-                // add markdown file extension for good measure
-                file_name.push_str(".md");
-                notes.push((format!("{file_name}"), h));
+            // one unreadable note should not cost the whole snapshot
+            let record = match self.record_from_entry(&entry).await {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!("snapshot skipped a record , {e}");
+                    continue;
+                }
+            };
+            // folder markers have nothing to save , placeholders have nothing at all
+            if (record.is_delete && !include_hidden)
+                || record.moved_to.is_some()
+                || is_folder(&record.id)
+                || record.kind == NoteKind::Placeholder
+            {
+                continue;
             }
+            // get_note merges the crdt authors
+            let mut note = match self.get_note(record.id.clone()).await {
+                Ok(note) => note,
+                Err(e) => {
+                    warn!("snapshot skipped {} , no body , {e}", record.id);
+                    continue;
+                }
+            };
+            note.is_delete = record.is_delete;
+            let mut text = front_matter(&note, &entry.author());
+            text.push_str(&note.text);
+            let hash = self.add_blob(text).await?;
+            notes.push((format!("{}.md", record.id), hash));
         }
-        // print!("{:#?}", notes);
+        notes.sort();
         let col = notes.into_iter().collect::<Collection>();
-        let col_hash = col.store(&self.0.blobs).await?;
-        // tag it for replication
-        let dt = Local::now().timestamp();
+        let tag = col.clone().store(&self.0.blobs).await?;
+        Ok((*tag.hash(), col))
+    }
+
+    // Take a snapshot , None if nothing changed since the newest one.
+    pub async fn snapshot(&self) -> Result<Option<String>> {
+        let (hash, _) = self.bounce_down(true).await?;
+        if let Some(newest) = self.list_snapshots().await?.first() {
+            if newest.hash == hash {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.tag_snapshot(hash).await?))
+    }
+
+    // Drop the snapshots the retention policy does not need.
+    // The untagged collections are left for the gc.
+    pub async fn prune_snapshots(&self) -> Result<usize> {
+        let now = Utc::now().timestamp();
+        let mut seen = HashSet::new();
+        let mut pruned = 0;
+        let prefix = self.snapshot_prefix();
+        // newest first , so the newest in each slot is kept
+        // only our own , the old shared ones are left alone
+        for snapshot in self.list_snapshots().await? {
+            if !snapshot.tag.starts_with(&prefix) {
+                continue;
+            }
+            if seen.insert(retention_bucket(snapshot.time, now)) {
+                continue;
+            }
+            self.0.blobs.tags().delete(snapshot.tag.as_str()).await?;
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+
+    // Everything the doc still points at , for the gc.
+    // Every author's entry , the revision chains behind them ,
+    // the bodies and the attachments the bodies link to.
+    pub async fn live_blobs(&self) -> Result<HashSet<Hash>> {
+        let entries = self.0.doc.get_many(Query::all()).await?;
+        let mut todo = Vec::new();
+        tokio::pin!(entries);
+        while let Some(entry) = entries.next().await {
+            todo.push(entry?.content_hash());
+        }
+        let mut live = HashSet::new();
+        while let Some(hash) = todo.pop() {
+            if !live.insert(hash) || !self.0.blobs.has(hash).await? {
+                continue;
+            }
+            let bytes = self.0.blobs.get_bytes(hash).await?;
+            let text = match StoredNote::parse("", &bytes) {
+                StoredNote::Record(record) => {
+                    todo.extend(record.prev);
+                    todo.extend(record.merged.iter().copied());
+                    live.insert(record.body);
+                    if !self.0.blobs.has(record.body).await? {
+                        continue;
+                    }
+                    self.record_text(&record).await?
+                }
+                StoredNote::Inline(note) => note.text,
+                // no telling what a newer version points at , keep everything
+                StoredNote::Unreadable { schema, .. } if schema > SCHEMA_VERSION => {
+                    bail!("records from a newer version , not collecting garbage")
+                }
+                StoredNote::Unreadable { .. } => continue,
+            };
+            for url in blob_urls(&text) {
+                if let Some((hash, _)) = parse_blob_url(&url) {
+                    live.insert(hash);
+                }
+            }
+        }
+        Ok(live)
+    }
+
+    // Keep a bounced down collection as a snapshot
+    pub async fn tag_snapshot(&self, hash: Hash) -> Result<String> {
+        let name = format!("{}{}", self.snapshot_prefix(), Utc::now().timestamp());
+        // hash_seq so the note blobs are kept along with it
         self.0
             .blobs
             .tags()
-            .set(format!("notes-{}", dt), &col_hash)
+            .set(name.clone(), HashAndFormat::hash_seq(hash))
             .await?;
-        // println!("notes bounce down {:?}", col_hash);
-        Ok(())
+        Ok(name)
     }
 
-    // TODO , need to specify the note backup the grab
-    // this should be a "recovery" interface.
-    #[allow(dead_code)]
-    pub async fn bounce_up(&self) -> Result<()> {
-        let tag = match self.0.blobs.tags().get("notes").await? {
-            Some(tag) => tag,
-            None => return Err(anyhow!("no notes tag")),
+    // Snapshot tags for this doc , the blob store is shared
+    fn snapshot_prefix(&self) -> String {
+        let id = self.0.doc.id().to_string();
+        format!("{SNAPSHOT_PREFIX}{}-", &id[..10])
+    }
+
+    // Recovery
+    // this doc's notes-* tags and the old undocumented ones , newest first.
+    pub async fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
+        let prefix = self.snapshot_prefix();
+        let mut snapshots = Vec::new();
+        let mut tags = self.0.blobs.tags().list_prefix(SNAPSHOT_PREFIX).await?;
+        while let Some(tag) = tags.next().await {
+            let tag = tag?;
+            let name = String::from_utf8_lossy(&tag.name.0).to_string();
+            let time = match name.strip_prefix(&prefix) {
+                Some(time) => time.parse().unwrap_or(0),
+                None => match name[SNAPSHOT_PREFIX.len()..].parse() {
+                    Ok(time) => time,
+                    // another doc's
+                    Err(_) => continue,
+                },
+            };
+            let count = match Collection::load(tag.hash, self.0.blobs.store()).await {
+                Ok(coll) => coll.len(),
+                Err(e) => {
+                    warn!("snapshot {name} is broken , {e}");
+                    continue;
+                }
+            };
+            snapshots.push(Snapshot {
+                tag: name,
+                hash: tag.hash,
+                time,
+                count,
+            });
+        }
+        snapshots.sort_by_key(|s| Reverse(s.time));
+        Ok(snapshots)
+    }
+
+    // Bring in a note from outside , keeping its times.
+    // false if it is already here and overwrite is off.
+    pub async fn import_note(&self, note: Note, overwrite: bool) -> Result<bool> {
+        if self.exists(&note.id).await? {
+            if !overwrite {
+                return Ok(false);
+            }
+            let id = note.id;
+            let Some(mut record) = self.get_record(&id).await? else {
+                bail!("no note called {id}");
+            };
+            // one revision on top , with the file's times , and showing again
+            let (body, size) = match record.kind {
+                NoteKind::Plain => self.put_body(note.text).await?,
+                NoteKind::Crdt => self.put_crdt_edit(&id, &note.text).await?,
+                NoteKind::Placeholder => bail!("{id} is read only"),
+            };
+            record.body = body;
+            record.size = size;
+            record.created = note.created;
+            record.updated = note.updated;
+            record.is_delete = false;
+            record.merged = Vec::new();
+            self.update_bytes(id.as_bytes(), record).await?;
+            return Ok(true);
+        }
+        let (body, size) = self.put_body(note.text).await?;
+        let record = NoteRecord {
+            id: note.id.clone(),
+            body,
+            size,
+            created: note.created,
+            updated: note.updated,
+            is_delete: false,
+            prev: None,
+            merged: Vec::new(),
+            kind: NoteKind::Plain,
+            moved_to: None,
+            schema: SCHEMA_VERSION,
+            inline: None,
         };
-        let coll = Collection::load(tag.hash, self.0.blobs.store()).await?;
-        println!("{:#?}", coll);
+        self.update_bytes(note.id.as_bytes(), record).await?;
+        Ok(true)
+    }
+
+    // A snapshot by tag name or collection hash
+    pub async fn find_snapshot(&self, name: &str) -> Result<Hash> {
+        if let Ok(hash) = Hash::from_str(name) {
+            return Ok(hash);
+        }
+        match self.0.blobs.tags().get(name).await? {
+            Some(tag) => Ok(tag.hash),
+            None => bail!("no snapshot called {name}"),
+        }
+    }
+
+    // The notes as they are now , in the same shape as a snapshot.
+    pub async fn current_notes(&self) -> Result<Vec<Note>> {
+        let (hash, _) = self.bounce_down(true).await?;
+        self.snapshot_notes(hash).await
+    }
+
+    // What is in a snapshot , the notes as they were.
+    pub async fn snapshot_notes(&self, hash: Hash) -> Result<Vec<Note>> {
+        let coll = Collection::load(hash, self.0.blobs.store()).await?;
+        let mut notes = Vec::new();
         for (name, hash) in coll.iter() {
-            let data_bytes = self.0.blobs.get_bytes(hash.as_bytes()).await?;
-            let text = String::from_utf8(data_bytes.to_vec())?;
-            self.create(name.clone(), text).await?
+            let data_bytes = self.0.blobs.get_bytes(*hash).await?;
+            let text = String::from_utf8(data_bytes.to_vec())
+                .with_context(|| format!("{name} is not utf8"))?;
+            notes.push(note_from_export(name, &text));
         }
-        Ok(())
+        Ok(notes)
+    }
+
+    // Put notes from a snapshot back , all of them if ids is None.
+    // Each one is a new revision so the history stays.
+    // Returns (restored, skipped).
+    pub async fn bounce_up(&self, hash: Hash, ids: Option<Vec<String>>) -> Result<(usize, usize)> {
+        let mut restored = 0;
+        let mut skipped = 0;
+        for note in self.snapshot_notes(hash).await? {
+            // hidden notes only come back if they are asked for
+            let wanted = match &ids {
+                Some(ids) => ids.contains(&note.id),
+                None => !note.is_delete,
+            };
+            if !wanted {
+                continue;
+            }
+            // same text , no point making a revision
+            let id = note.id.clone();
+            let current = self.get_note(id.clone()).await?;
+            if !current.is_missing() && !current.is_delete && current.text == note.text {
+                continue;
+            }
+            match self.restore_copy(note).await {
+                Ok(()) => restored += 1,
+                Err(e) => {
+                    warn!("could not restore {id} , {e}");
+                    skipped += 1;
+                }
+            }
+        }
+        Ok((restored, skipped))
+    }
+
+    // Write a snapshot copy back as a new revision , and visible.
+    async fn restore_copy(&self, note: Note) -> Result<()> {
+        let id = self.resolve_id(note.id).await?;
+        if self.get_record(&id).await?.is_some() {
+            self.update_note(id.clone(), note.text).await?;
+            return self.set_hidden(id, false).await;
+        }
+        // gone from the doc , bring it back with its birthday
+        let now = Utc::now().timestamp();
+        // old exports may not have the times
+        let created = match note.created {
+            0 => now,
+            t => t,
+        };
+        let (body, size) = self.put_body(note.text).await?;
+        let record = NoteRecord {
+            id: id.clone(),
+            body,
+            size,
+            created,
+            updated: now,
+            is_delete: false,
+            prev: None,
+            merged: Vec::new(),
+            kind: NoteKind::Plain,
+            moved_to: None,
+            schema: SCHEMA_VERSION,
+            inline: None,
+        };
+        self.update_bytes(id.as_bytes(), record).await
     }
     // End direct doc manipulation
 }
 
 // mmm notes.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn a_newer_record_reads_as_a_placeholder() {
+        // what a later version might write
+        let record = json!({
+            "id": "from/the/future",
+            "schema": 99,
+            "body": "not a hash we know",
+            "blocks": [],
+        });
+        let note = match StoredNote::parse("from/the/future", record.to_string().as_bytes()) {
+            StoredNote::Unreadable { id, schema } => {
+                NoteRecord::placeholder(id, schema).to_note(placeholder_text(schema))
+            }
+            _ => panic!("a newer record should not parse"),
+        };
+        // what cat --json prints
+        let out: Value =
+            serde_json::from_str(&serde_json::to_string_pretty(&note).unwrap()).unwrap();
+        assert_eq!(out["id"], "from/the/future");
+        assert_eq!(out["kind"], "Placeholder");
+        assert!(out["text"].as_str().unwrap().contains("newer version"));
+    }
+}
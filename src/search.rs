@@ -0,0 +1,243 @@
+// Local full text search over the note bodies
+// lives in the worker and is kept up to date as notes change
+// (local saves and remote inserts).
+// Ranking is BM25 , snippets are cut around the first hit.
+
+use std::collections::{HashMap, HashSet};
+
+// BM25 tuning , the usual values
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+// characters either side of the hit in a snippet
+const SNIPPET_CONTEXT: usize = 60;
+
+// A single search result
+#[derive(Clone, Debug)]
+pub struct SearchHit {
+    pub id: String,
+    pub score: f32,
+    // snippet pieces , true if the piece matched the query
+    pub snippet: Vec<(String, bool)>,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    // the text for snippets
+    texts: HashMap<String, String>,
+    // token count per note
+    lengths: HashMap<String, usize>,
+    // term -> note id -> term frequency
+    postings: HashMap<String, HashMap<String, u32>>,
+}
+
+// Lower case words , everything else is a separator
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn count(&self) -> usize {
+        self.texts.len()
+    }
+
+    // Add or replace a note , the id is searchable as well
+    pub fn index(&mut self, id: &str, text: &str) {
+        self.remove(id);
+        let mut tokens = tokenize(id);
+        tokens.extend(tokenize(text));
+        self.lengths.insert(id.to_string(), tokens.len());
+        for token in tokens {
+            *self
+                .postings
+                .entry(token)
+                .or_default()
+                .entry(id.to_string())
+                .or_default() += 1;
+        }
+        self.texts.insert(id.to_string(), text.to_string());
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if self.texts.remove(id).is_none() {
+            return;
+        }
+        self.lengths.remove(id);
+        self.postings.retain(|_, notes| {
+            notes.remove(id);
+            !notes.is_empty()
+        });
+    }
+
+    // Ranked results , best first
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let terms: HashSet<String> = tokenize(query).into_iter().collect();
+        if terms.is_empty() || self.texts.is_empty() {
+            return Vec::new();
+        }
+        let count = self.texts.len() as f32;
+        let avg_len = self.lengths.values().sum::<usize>() as f32 / count;
+        let mut scores: HashMap<&String, f32> = HashMap::new();
+        for term in terms.iter() {
+            let Some(notes) = self.postings.get(term) else {
+                continue;
+            };
+            let idf = ((count - notes.len() as f32 + 0.5) / (notes.len() as f32 + 0.5) + 1.0).ln();
+            for (id, tf) in notes.iter() {
+                let tf = *tf as f32;
+                let len = *self.lengths.get(id).unwrap_or(&0) as f32;
+                let score = idf * (tf * (K1 + 1.0)) / (tf + K1 * (1.0 - B + B * len / avg_len));
+                *scores.entry(id).or_default() += score;
+            }
+        }
+        let mut hits: Vec<SearchHit> = scores
+            .into_iter()
+            .map(|(id, score)| SearchHit {
+                id: id.clone(),
+                score,
+                snippet: self.snippet(id, &terms),
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        hits
+    }
+
+    // Cut a window of text around the first hit and mark the matches
+    fn snippet(&self, id: &str, terms: &HashSet<String>) -> Vec<(String, bool)> {
+        let Some(text) = self.texts.get(id) else {
+            return Vec::new();
+        };
+        // word spans in char positions
+        let chars: Vec<char> = text.chars().collect();
+        let mut words = Vec::new();
+        let mut start = None;
+        for (pos, c) in chars.iter().enumerate() {
+            match (c.is_alphanumeric(), start) {
+                (true, None) => start = Some(pos),
+                (false, Some(s)) => {
+                    words.push((s, pos));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            words.push((s, chars.len()));
+        }
+        let matched: Vec<(usize, usize)> = words
+            .into_iter()
+            .filter(|(s, e)| {
+                let word: String = chars[*s..*e].iter().collect();
+                terms.contains(&word.to_lowercase())
+            })
+            .collect();
+        // only matched the id , show the start of the note
+        let first = matched.first().map(|(s, _)| *s).unwrap_or(0);
+        let window_start = first.saturating_sub(SNIPPET_CONTEXT);
+        let window_end = (first + SNIPPET_CONTEXT * 2).min(chars.len());
+
+        let mut pieces = Vec::new();
+        let mut pos = window_start;
+        if window_start > 0 {
+            pieces.push((String::from("…"), false));
+        }
+        for (s, e) in matched {
+            if s < pos || e > window_end {
+                continue;
+            }
+            pieces.push((chars[pos..s].iter().collect(), false));
+            pieces.push((chars[s..e].iter().collect(), true));
+            pos = e;
+        }
+        pieces.push((chars[pos..window_end].iter().collect(), false));
+        if window_end < chars.len() {
+            pieces.push((String::from("…"), false));
+        }
+        // keep it on one line
+        pieces
+            .into_iter()
+            .map(|(text, hit)| (text.replace(['\n', '\r'], " "), hit))
+            .filter(|(text, _)| !text.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(notes: &[(&str, &str)]) -> SearchIndex {
+        let mut search = SearchIndex::new();
+        for (id, text) in notes {
+            search.index(id, text);
+        }
+        search
+    }
+
+    #[test]
+    fn empty_query_finds_nothing() {
+        let search = index(&[("a", "some text")]);
+        assert!(search.search("", 10).is_empty());
+        assert!(search.search(" ,. ", 10).is_empty());
+    }
+
+    #[test]
+    fn empty_index_finds_nothing() {
+        assert!(SearchIndex::new().search("text", 10).is_empty());
+    }
+
+    #[test]
+    fn single_note_scores_above_zero() {
+        let search = index(&[("only", "the one and only note")]);
+        let hits = search.search("note", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, "only");
+        assert!(hits[0].score > 0.0);
+    }
+
+    #[test]
+    fn more_matches_rank_first() {
+        let search = index(&[
+            ("once", "rust is mentioned once here among other words"),
+            ("often", "rust rust rust"),
+            ("never", "nothing to see"),
+        ]);
+        let hits = search.search("Rust", 10);
+        let ids: Vec<&str> = hits.iter().map(|h| h.id.as_str()).collect();
+        assert_eq!(ids, ["often", "once"]);
+    }
+
+    #[test]
+    fn removed_notes_drop_out() {
+        let mut search = index(&[("a", "apple"), ("b", "apple pie")]);
+        search.remove("a");
+        assert_eq!(search.count(), 1);
+        let hits = search.search("apple", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, "b");
+    }
+
+    #[test]
+    fn snippet_marks_the_hit() {
+        let search = index(&[("a", "find the needle here")]);
+        let hits = search.search("needle", 10);
+        let marked: Vec<&str> = hits[0]
+            .snippet
+            .iter()
+            .filter(|(_, hit)| *hit)
+            .map(|(text, _)| text.as_str())
+            .collect();
+        assert_eq!(marked, ["needle"]);
+    }
+}
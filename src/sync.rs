@@ -0,0 +1,338 @@
+// Sync supervisor
+// one task per open doc , it owns the doc subscription and keeps it alive.
+// A dropped subscription or a dial that never syncs backs off
+// ( doubling , capped , with jitter ) and tries again , forever ,
+// at the cap it is a slow steady retry. A good sync resets the backoff.
+// One peer failing is shown against that peer , the rest carry on.
+// The state , the peers and the pending content go to the gui
+// so it can say what is going on.
+// Dropping the stop sender ends the supervisor ( leave , replace , shutdown ).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use async_channel::{Receiver, Sender};
+use chrono::Utc;
+use iroh::NodeAddr;
+use iroh_blobs::Hash;
+use iroh_docs::{ContentStatus, Entry, engine::LiveEvent};
+use n0_future::StreamExt;
+use tracing::{info, warn};
+
+use crate::comms::{Command, MessageOut};
+use crate::notes::Notes;
+
+// first wait after a failure
+const BACKOFF_START: Duration = Duration::from_secs(2);
+// slowest retry , it never gives up
+const BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+// with peers to dial , no good sync this long after connecting is a failure
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+
+// What the supervisor is up to
+#[derive(Clone, Debug)]
+pub enum SyncState {
+    Connecting,
+    // nobody to dial , listening for peers that come to us
+    Waiting,
+    Synced,
+    // failures in a row and when the next go is
+    BackingOff { attempt: u32, retry_at: Instant },
+}
+
+impl fmt::Display for SyncState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncState::Connecting => write!(f, "connecting"),
+            SyncState::Waiting => write!(f, "waiting for peers"),
+            SyncState::Synced => write!(f, "synced"),
+            SyncState::BackingOff { attempt, retry_at } => {
+                let secs = retry_at.saturating_duration_since(Instant::now()).as_secs();
+                write!(f, "backing off ({attempt}) , next retry in {secs} s")
+            }
+        }
+    }
+}
+
+// One peer as this doc sees it
+#[derive(Clone, Debug, Default)]
+pub struct PeerSync {
+    pub connected: bool,
+    // unix time of the last good sync
+    pub last_sync: Option<i64>,
+    // why the last sync with them failed , cleared by a good one
+    pub last_error: Option<String>,
+}
+
+// Everything the gui shows about a doc's sync
+#[derive(Clone, Debug)]
+pub struct SyncStatus {
+    pub state: SyncState,
+    // by short node id , kept after they go so the last sync still shows
+    pub peers: BTreeMap<String, PeerSync>,
+    // remote entries still waiting for their content
+    pub pending: usize,
+}
+
+impl SyncStatus {
+    pub fn new() -> Self {
+        Self {
+            state: SyncState::Connecting,
+            peers: BTreeMap::new(),
+            pending: 0,
+        }
+    }
+
+    pub fn connected(&self) -> usize {
+        self.peers.values().filter(|p| p.connected).count()
+    }
+
+    // Newest good sync with anyone
+    pub fn last_sync(&self) -> Option<i64> {
+        self.peers.values().filter_map(|p| p.last_sync).max()
+    }
+
+    fn peer(&mut self, id: String) -> &mut PeerSync {
+        self.peers.entry(id).or_default()
+    }
+}
+
+// Capped exponential backoff
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self { attempt: 0 }
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    // Next wait , doubling up to the cap
+    // the last quarter is random so peers don't all come back at once.
+    pub fn next(&mut self) -> Duration {
+        let base = BACKOFF_START
+            .saturating_mul(1 << self.attempt.min(16))
+            .min(BACKOFF_MAX);
+        self.attempt += 1;
+        let quarter = base.as_millis() as u64 / 4;
+        let jitter = Duration::from_millis(rand::random_range(0..=quarter));
+        base - Duration::from_millis(quarter) + jitter
+    }
+}
+
+// What a session needs , the same for the life of the supervisor
+struct Doc {
+    key: String,
+    notes: Notes,
+    peers: Option<Vec<NodeAddr>>,
+    mess: MessageOut,
+    command_tx: Sender<Command>,
+}
+
+// The supervisor , lives in the worker task pool
+pub async fn supervise(
+    key: String,
+    notes: Notes,
+    peers: Option<Vec<NodeAddr>>,
+    mess: MessageOut,
+    command_tx: Sender<Command>,
+    stopped: Receiver<()>,
+) {
+    info!("sync for {key} started");
+    let doc = Doc {
+        key: key.clone(),
+        notes,
+        peers,
+        mess: mess.clone(),
+        command_tx,
+    };
+    let mut backoff = Backoff::new();
+    let mut status = SyncStatus::new();
+    let mut resubscribe = false;
+    loop {
+        let failed = tokio::select! {
+            failed = session(&doc, &mut backoff, &mut status, resubscribe) => failed,
+            _ = stopped.recv() => break,
+        };
+        resubscribe = true;
+        let wait = backoff.next();
+        warn!("sync for {key} dropped , {failed} , retry in {wait:?}");
+        // nobody is connected to a dropped subscription
+        for peer in status.peers.values_mut() {
+            peer.connected = false;
+        }
+        status.pending = 0;
+        status.state = SyncState::BackingOff {
+            attempt: backoff.attempt(),
+            retry_at: Instant::now() + wait,
+        };
+        let _ = mess.sync_status(key.clone(), status.clone()).await;
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = stopped.recv() => break,
+        }
+    }
+    info!("sync for {key} stopped");
+}
+
+// One subscription , only comes back with why it stopped
+async fn session(
+    doc: &Doc,
+    backoff: &mut Backoff,
+    status: &mut SyncStatus,
+    resubscribe: bool,
+) -> anyhow::Error {
+    match run_session(doc, backoff, status, resubscribe).await {
+        Ok(()) => anyhow!("event stream ended"),
+        Err(err) => err,
+    }
+}
+
+// The subscription stays up for as long as the event stream does.
+// A failed sync is that peer's problem , it shows against the peer.
+// No good sync in time means dialing again after a backoff.
+async fn run_session(
+    doc: &Doc,
+    backoff: &mut Backoff,
+    status: &mut SyncStatus,
+    resubscribe: bool,
+) -> Result<()> {
+    let key = doc.key.as_str();
+    // a fresh subscription , nothing is waiting on it yet
+    status.pending = 0;
+    status.state = SyncState::Connecting;
+    doc.mess.sync_status(key.to_string(), status.clone()).await?;
+    if let Some(peers) = &doc.peers {
+        doc.notes.share(peers.clone()).await?;
+    }
+    let events = doc.notes.doc_subscribe().await?;
+    // anything that came in while nobody was listening
+    if resubscribe {
+        doc.command_tx.send(Command::Reindex(key.to_string())).await?;
+    }
+    if doc.peers.is_none() {
+        status.state = SyncState::Waiting;
+        doc.mess.sync_status(key.to_string(), status.clone()).await?;
+    }
+    let deadline = tokio::time::sleep(SYNC_TIMEOUT);
+    let mut synced = false;
+    // the deadline is a backoff wait , dial when it runs out
+    let mut redial = false;
+    // remote entries waiting on their content
+    let mut pending: HashMap<Hash, String> = HashMap::new();
+
+    tokio::pin!(events);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else {
+                    return Ok(());
+                };
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("sync event for {key} , {e}");
+                        continue;
+                    }
+                };
+                // only tell the gui when something it shows moved
+                let mut changed = false;
+                match event {
+                    LiveEvent::NeighborUp(peer) => {
+                        status.peer(peer.fmt_short().to_string()).connected = true;
+                        changed = true;
+                    }
+                    LiveEvent::NeighborDown(peer) => {
+                        status.peer(peer.fmt_short().to_string()).connected = false;
+                        changed = true;
+                    }
+                    LiveEvent::InsertRemote { ref entry, content_status, .. } => {
+                        // only useful once the content is here
+                        if let Some(id) = entry_id(entry) {
+                            match content_status {
+                                ContentStatus::Complete => {
+                                    doc.command_tx.send(Command::RemoteChange(key.to_string(), id)).await?;
+                                }
+                                _ => {
+                                    pending.insert(entry.content_hash(), id);
+                                }
+                            }
+                        }
+                    }
+                    LiveEvent::ContentReady { hash } => {
+                        if let Some(id) = pending.remove(&hash) {
+                            doc.command_tx.send(Command::RemoteChange(key.to_string(), id)).await?;
+                        }
+                    }
+                    LiveEvent::SyncFinished(sync_event) => {
+                        let peer = status.peer(sync_event.peer.fmt_short().to_string());
+                        match sync_event.result {
+                            Ok(_) => {
+                                peer.last_sync = Some(Utc::now().timestamp());
+                                peer.last_error = None;
+                                synced = true;
+                                redial = false;
+                                backoff.reset();
+                                status.state = SyncState::Synced;
+                            }
+                            Err(err) => {
+                                warn!("sync for {key} with {} failed , {err}", sync_event.peer.fmt_short());
+                                peer.last_error = Some(err.to_string());
+                            }
+                        }
+                        changed = true;
+                    }
+                    // the sync's downloads are done , some may have failed
+                    // so those stay pending until their ContentReady
+                    LiveEvent::PendingContentReady => {
+                        doc.command_tx.send(Command::GetNotes).await?;
+                    }
+                    _ => {}
+                }
+                if changed || status.pending != pending.len() {
+                    status.pending = pending.len();
+                    doc.mess.sync_status(key.to_string(), status.clone()).await?;
+                }
+            }
+            _ = &mut deadline, if doc.peers.is_some() && !synced => {
+                if redial {
+                    redial = false;
+                    status.state = SyncState::Connecting;
+                    if let Some(peers) = &doc.peers {
+                        if let Err(e) = doc.notes.share(peers.clone()).await {
+                            warn!("dialing peers for {key} , {e}");
+                        }
+                    }
+                    deadline.as_mut().reset(tokio::time::Instant::now() + SYNC_TIMEOUT);
+                } else {
+                    let wait = backoff.next();
+                    warn!("no sync for {key} in {} s , dialing again in {wait:?}", SYNC_TIMEOUT.as_secs());
+                    status.state = SyncState::BackingOff {
+                        attempt: backoff.attempt(),
+                        retry_at: Instant::now() + wait,
+                    };
+                    redial = true;
+                    deadline.as_mut().reset(tokio::time::Instant::now() + wait);
+                }
+                doc.mess.sync_status(key.to_string(), status.clone()).await?;
+            }
+        }
+    }
+}
+
+// Note id from a doc entry key (strip the null byte)
+fn entry_id(entry: &Entry) -> Option<String> {
+    let key = entry.key().strip_suffix(&[0]).unwrap_or(entry.key());
+    String::from_utf8(key.to_owned()).ok()
+}
@@ -2,20 +2,40 @@
 // Worker
 // --------------------------
 
-use std::{str::FromStr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::comms::{Command, Config, Event, MessageOut};
-use crate::notes::Notes;
-use anyhow::Result;
+use crate::api::{Api, ApiCall, ApiError, note_id, to_json};
+use crate::attach::{attachment_markdown, parse_blob_url};
+use crate::comms::{Command, Config, DocSet, Event, MessageOut};
+use crate::diff::diff_notes;
+use crate::import;
+use crate::notes::{Notes, Protected, is_folder};
+use crate::links::LinkIndex;
+use crate::mirror::{Mirror, Written, note_path};
+use crate::search::SearchIndex;
+use crate::sync;
+use anyhow::{Context, Result, anyhow, bail};
 use async_channel::{Receiver, Sender};
+use futures_buffered::BufferedStreamExt;
 use iroh::protocol::Router;
 // use iroh::protocol::Router;
 use iroh::{Endpoint, NodeAddr, SecretKey};
-use iroh_blobs::BlobsProtocol;
-use iroh_docs::{AuthorId, NamespaceId};
-use iroh_docs::{DocTicket, engine::LiveEvent, protocol::Docs};
+use chrono::{Local, Utc};
+use iroh_blobs::{
+    BlobsProtocol, Hash,
+    store::{GcConfig, ProtectCb, ProtectOutcome, fs::options::Options},
+};
+use iroh_docs::engine::ProtectCallbackHandler;
+use iroh_docs::{AuthorId, Capability, NamespaceId};
+use iroh_docs::{DocTicket, protocol::Docs};
 use iroh_gossip::net::Gossip;
-use n0_future::{FuturesUnordered, Stream, StreamExt};
+use n0_future::{FuturesUnordered, StreamExt};
 use tokio::time::{Instant, interval};
 use tracing::{error, info, warn};
 
@@ -25,14 +45,56 @@ pub struct Worker {
     pub mess: MessageOut,
     pub timer_out: Sender<TimerCommands>,
     pub blobs: BlobsProtocol,
-    _endpoint: Endpoint,
+    endpoint: Endpoint,
     pub notes: Option<Notes>,
     _gossip: Gossip,
     pub docs: Docs,
     pub config: Config,
-    _router: Router,
+    router: Router,
+    // held while running , see lock_store
+    _store_lock: std::fs::File,
     pub tasks: FuturesUnordered<n0_future::boxed::BoxFuture<()>>,
-    retry: u32,
+    // every doc set that is open , by doc key
+    // notes above is the one the gui is looking at.
+    open: HashMap<String, OpenDoc>,
+    search: SearchIndex,
+    links: LinkIndex,
+    // what the blob gc has to keep
+    protected: Protected,
+    // why the gc is not running , told to the gui when it changes
+    gc_paused: Option<String>,
+    last_snapshot: i64,
+    snapshots_scheduled: bool,
+    last_purge: i64,
+    // live copy of the notes in a folder , when turned on
+    mirror: Option<Mirror>,
+    mirror_scheduled: bool,
+    // local http api , when turned on
+    api: Option<Api>,
+}
+
+// Most results to send to the gui
+const SEARCH_LIMIT: usize = 50;
+// progress message every this many exported or imported notes
+const PROGRESS_EVERY: usize = 10;
+// don't flood the messages with skipped files
+const MAX_SKIP_REPORT: usize = 20;
+// how often the scheduler checks if a snapshot is due
+const SNAPSHOT_CHECK: Duration = Duration::from_secs(60);
+// how often the blob store collects garbage
+const GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// how often old notes are purged from the trash , seconds
+const PURGE_EVERY: i64 = 60 * 60;
+// how often the mirror folder is checked for edits
+const MIRROR_POLL: Duration = Duration::from_secs(2);
+// note bodies fetched at once on a full reindex
+const REINDEX_FETCHES: usize = 16;
+
+// A doc with its own sync supervisor
+struct OpenDoc {
+    notes: Notes,
+    // dropped to stop the supervisor
+    _stop: Sender<()>,
 }
 
 pub struct WorkerHandle {
@@ -62,11 +124,18 @@ impl Worker {
                 .build()
                 .expect("failed to start tokio runtime");
             rt.block_on(async move {
-                let mut worker = Worker::start(command_rx, command_tx_self, event_tx, config)
-                    .await
-                    .expect("Worker failed to start");
-                if let Err(err) = worker.run().await {
-                    warn!("worker stopped with error {err:?}");
+                let mess = MessageOut::new(event_tx.clone());
+                match Worker::start(command_rx, command_tx_self, event_tx, config).await {
+                    Ok(mut worker) => {
+                        if let Err(err) = worker.run().await {
+                            warn!("worker stopped with error {err:?}");
+                        }
+                    }
+                    // tell whoever is listening , the store may be in use
+                    Err(err) => {
+                        error!("worker failed to start {err:?}");
+                        let _ = mess.error(format!("{err}").as_str()).await;
+                    }
                 }
             })
         });
@@ -89,6 +158,10 @@ impl Worker {
         // Run the timer
         timer.run(timer_in);
 
+        // one worker per store , the gui , cli and daemon all share it
+        // taken first , a second copy should stop before it binds anything
+        let store_lock = lock_store(&config.store_path)?;
+
         // Create the endpoint
         let secret_key = SecretKey::from_str(config.secret_key.as_str())?;
         let endpoint = Endpoint::builder()
@@ -96,13 +169,24 @@ impl Worker {
             .discovery_n0()
             .bind()
             .await?;
+        // peers put this in their config to sync with us
+        info!("node id {}", endpoint.node_id());
 
         // Create the blob store
+        // gc keeps what the docs and the notes point at
+        let protected: Protected = Default::default();
+        let (docs_protect, docs_cb) = ProtectCallbackHandler::new();
         let mut blob_path = config.store_path.clone();
         blob_path.push("blobs");
-        let store = iroh_blobs::store::fs::FsStore::load(&blob_path)
-            .await
-            .unwrap();
+        let mut options = Options::new(&blob_path);
+        options.gc = Some(GcConfig {
+            interval: GC_INTERVAL,
+            add_protected: Some(gc_protect(protected.clone(), docs_cb)),
+        });
+        let store =
+            iroh_blobs::store::fs::FsStore::load_with_opts(blob_path.join("blobs.db"), options)
+                .await
+                .unwrap();
         let blobs = iroh_blobs::BlobsProtocol::new(&store, None);
 
         // Create the gossip
@@ -112,6 +196,7 @@ impl Worker {
 
         let docs_path = config.store_path.clone();
         let docs = Docs::persistent(docs_path)
+            .protect_handler(docs_protect)
             .spawn(endpoint.clone(), (*blobs).clone(), gossip.clone())
             .await?;
 
@@ -138,14 +223,25 @@ impl Worker {
             mess,
             timer_out,
             blobs,
-            _endpoint: endpoint,
+            endpoint,
             _gossip: gossip,
             docs,
             config,
             notes,
-            _router: router,
+            router,
+            _store_lock: store_lock,
             tasks,
-            retry: 1,
+            open: HashMap::new(),
+            search: SearchIndex::new(),
+            links: LinkIndex::new(),
+            protected,
+            gc_paused: None,
+            last_snapshot: 0,
+            snapshots_scheduled: false,
+            last_purge: 0,
+            mirror: None,
+            mirror_scheduled: false,
+            api: None,
         })
     }
 
@@ -158,11 +254,16 @@ impl Worker {
                 command = self.command_rx.recv() => {
                     let command = command?;
                     // if the handle fails send a red message to the app.
+                    let shutdown = matches!(command, Command::Shutdown);
                     if let Err(err ) = self.handle_command(command).await{
                         self.mess.error(format!("{}",err).as_str()).await?;
                         warn!("command failed {err}");
                         self.mess.finished().await?;
                     }
+                    if shutdown {
+                        self.mess.stopped().await?;
+                        return Ok(());
+                    }
                 }
                 // Run everything in the task pool
                 // this needs a bit more defn.
@@ -175,6 +276,9 @@ impl Worker {
     // handle the incoming commands from the egui
     // this is where the main actions for the worker happen
     async fn handle_command(&mut self, command: Command) -> Result<()> {
+        if command.is_write() {
+            self.check_writable()?;
+        }
         match command {
             Command::Setup { callback } => {
                 // lodge the redraw callback into the message updater
@@ -184,48 +288,56 @@ impl Worker {
                 return Ok(());
             }
 
-            // Attach the Document to the mother ship
-            // and increment the retry
-            Command::Attach => {
-                // this happens when the system trys to reattach
-                // rather than a command from the egui
-                // weird by OK
-                if let Some(notes) = &self.notes {
-                    self.run_sync(notes.clone(), self.command_tx.clone())
-                        .await?;
+            // Reconnect a doc now , a fresh supervisor with the backoff reset
+            Command::Attach(key) => {
+                if self.open.contains_key(&key) {
+                    self.run_sync(&key).await?;
                 }
                 return Ok(());
             }
             // Already set up in config ( attach by id )
+            // every doc set gets opened , this one is shown.
             Command::DocId(id) => {
                 info!("Create doc from id {}", id);
-                let id = NamespaceId::from_str(id.as_str())?;
-                let author_id = self.author().await?;
-                let notes =
-                    Notes::from_id(id, author_id, self.blobs.clone(), self.docs.clone()).await?;
-                // Subscribe and get synced
-                warn!("Start sync");
-                // Start the subscripion
-                self.run_sync(notes.clone(), self.command_tx.clone())
-                    .await?;
-                warn!("Finish sync");
-                self.notes = Some(notes);
+                self.migrate_config().await?;
+                let keys: Vec<String> = self
+                    .config
+                    .doc_sets
+                    .iter()
+                    .map(|d| d.doc_key.clone())
+                    .collect();
+                for key in keys.iter().filter(|k| **k != id) {
+                    // one broken doc should not stop the others
+                    if let Err(e) = self.open_doc(key).await {
+                        self.mess
+                            .error(format!("could not open {key} , {e}").as_str())
+                            .await?;
+                    }
+                }
+                self.activate(id).await?;
+                self.start_api().await?;
+                // read only flags ( or a new api token ) may have changed
+                self.save_config().await?;
                 return Ok(());
             }
 
-            // No doc in the config , use a doc share ticket.
-            Command::DocTicket(ticket) => {
+            // Join a doc from a share ticket , at any time.
+            Command::JoinDoc(name, ticket) => {
                 // schnaffle the ticket into the config
-                let doc_ticket = DocTicket::from_str(ticket.as_str())?;
+                let doc_ticket = DocTicket::from_str(ticket.trim())?;
                 info!("{:#?}", &doc_ticket);
-                self.config.doc_key = Some(doc_ticket.capability.id().to_string());
+                let key = doc_ticket.capability.id().to_string();
+                let read_only = !matches!(doc_ticket.capability, Capability::Write(_));
+                if self.config.doc_set(&key).is_some() {
+                    bail!("already have that doc");
+                }
                 // just save the node ids , not the whole address
                 let nodes = doc_ticket
                     .nodes
                     .iter()
                     .map(|n| NodeAddr::new(n.node_id))
                     .collect();
-                self.config.mothership = Some(nodes);
+                let name = doc_set_name(&self.config, name);
                 // Create a new author if none ( not using default notes id )
                 let author_id = self.author().await?;
 
@@ -233,26 +345,105 @@ impl Worker {
 
                 // Make  new note set
                 let notes = Notes::new(
-                    Some(ticket),
+                    Some(ticket.trim().to_string()),
                     author_id,
                     self.blobs.clone(),
                     self.docs.clone(),
+                    &self.endpoint,
+                    self.protected.clone(),
                 )
                 .await?;
-
-                warn!("Start Sync");
-                // create the task to replicate, this needs a retry syste
-                self.run_sync(notes.clone(), self.command_tx.clone())
-                    .await?;
+                self.config.doc_sets.push(DocSet {
+                    name: name.clone(),
+                    doc_key: key.clone(),
+                    mothership: Some(nodes),
+                    read_only,
+                });
+                self.add_open(key.clone(), notes).await?;
 
                 // nice some notes
-                self.notes = Some(notes);
+                self.activate(key).await?;
                 // if there is a new author , push it up to the app and config file
                 self.save_config().await?;
-                info!("exit new ticket");
+                self.mess.good(format!("joined {name}").as_str()).await?;
+                let note_list = self.note_list().await;
+                self.mess.send_note_list(note_list).await?;
                 // looks good.
                 return Ok(());
             }
+
+            // Fresh empty doc , straight to sharing it
+            Command::NewDoc(name) => {
+                let name = doc_set_name(&self.config, name);
+                let author_id = self.author().await?;
+                let notes = Notes::new(
+                    None,
+                    author_id,
+                    self.blobs.clone(),
+                    self.docs.clone(),
+                    &self.endpoint,
+                    self.protected.clone(),
+                )
+                .await?;
+                let key = notes.doc_key();
+                info!("new doc {key}");
+                // nobody to sync with yet , they join with the ticket
+                self.config.doc_sets.push(DocSet {
+                    name: name.clone(),
+                    doc_key: key.clone(),
+                    mothership: None,
+                    read_only: false,
+                });
+                self.add_open(key.clone(), notes.clone()).await?;
+                self.activate(key).await?;
+                self.save_config().await?;
+                self.mess.good(format!("made {name}").as_str()).await?;
+                self.mess.send_note_list(Vec::new()).await?;
+                self.mess.share_ticket(notes.ticket(true).await?).await?;
+                return Ok(());
+            }
+
+            // Show another doc set
+            Command::SwitchDoc(key) => {
+                if self.config.doc_set(&key).is_none() {
+                    bail!("no doc set {key}");
+                }
+                self.activate(key).await?;
+                self.save_config().await?;
+                let note_list = self.note_list().await;
+                self.mess.send_note_list(note_list).await?;
+                return Ok(());
+            }
+
+            // Stop syncing a doc and delete our copy of it
+            // the other peers keep theirs.
+            Command::LeaveDoc(key) => {
+                let Some(doc_set) = self.config.doc_set(&key).cloned() else {
+                    bail!("no doc set {key}");
+                };
+                // dropping it stops the sync task
+                self.open.remove(&key);
+                self.config.doc_sets.retain(|d| d.doc_key != key);
+                if self.config.doc_key.as_ref() == Some(&key) {
+                    self.notes = None;
+                    self.mirror = None;
+                    self.config.doc_key = None;
+                    if let Some(next) = self.config.doc_sets.first().map(|d| d.doc_key.clone()) {
+                        self.activate(next).await?;
+                    } else {
+                        self.reindex_all().await?;
+                    }
+                }
+                self.docs.drop_doc(NamespaceId::from_str(&key)?).await?;
+                self.refresh_protected().await;
+                self.save_config().await?;
+                self.mess
+                    .info(format!("left {} , local copy deleted", doc_set.name).as_str())
+                    .await?;
+                let note_list = self.note_list().await;
+                self.mess.send_note_list(note_list).await?;
+                return Ok(());
+            }
             // Clear the timer in egui
             Command::ResetTimer => {
                 self.reset_timer().await?;
@@ -262,7 +453,19 @@ impl Worker {
 
             // Confing from the egui application
             Command::SendConfig(config) => {
+                let mirror_changed = config.mirror != self.config.mirror;
+                let api_changed = config.api != self.config.api
+                    || config.api_port != self.config.api_port
+                    || config.api_token != self.config.api_token;
                 self.config = config;
+                if api_changed {
+                    self.start_api().await?;
+                }
+                if mirror_changed {
+                    self.start_mirror().await?;
+                    // writes everything out when turned on
+                    self.reindex_all().await?;
+                }
                 return Ok(());
             }
 
@@ -270,8 +473,9 @@ impl Worker {
             Command::SaveNote(id, text) => {
                 warn!("note info => \"{}\" \"{}\"", id, text);
                 if let Some(notes) = &self.notes {
-                    notes.update_note(id, text).await?;
+                    notes.update_note(id.clone(), text).await?;
                 }
+                self.reindex_note(id).await;
                 return Ok(());
             }
 
@@ -279,7 +483,23 @@ impl Worker {
             Command::NewNote(id, text) => {
                 warn!("create note => \"{}\" \"{}\"", id, text);
                 if let Some(notes) = &self.notes {
-                    notes.create(id, text).await?;
+                    notes.create(id.clone(), text).await?;
+                }
+                self.reindex_note(id).await;
+                return Ok(());
+            }
+
+            // A sync subscription came back , changes while it was down
+            // never came through as remote changes.
+            Command::Reindex(key) => {
+                let Some(open) = self.open.get(&key) else {
+                    return Ok(());
+                };
+                open.notes.forget_conflicts();
+                if self.config.doc_key.as_ref() == Some(&key) {
+                    self.reindex_all().await?;
+                    // straight out , sending ourselves a command can wait on a full queue
+                    self.send_notes().await?;
                 }
                 return Ok(());
             }
@@ -287,37 +507,106 @@ impl Worker {
             // Get a list of existing notes
             // Not not ids but actual names
             Command::GetNotes => {
-                if let Some(notes) = &self.notes {
-                    let note_list = notes.get_note_vec().await;
-                    self.mess.send_note_list(note_list).await?;
-                }
+                self.send_notes().await?;
                 return Ok(());
             }
 
             // Grab a single note
             Command::GetNote(id) => {
                 if let Some(notes) = &self.notes {
-                    let note = notes.get_note(id).await?;
+                    let note = notes.get_note(id.clone()).await?;
+                    let backlinks = self.links.backlinks(&note.id);
+                    self.mess.send_backlinks(note.id.clone(), backlinks).await?;
                     self.mess.send_note(note).await?;
+                    // tell the gui if the authors disagree
+                    if let Some(versions) = notes.get_versions(id.clone()).await? {
+                        self.mess.info("note has conflicting versions").await?;
+                        self.mess.send_versions(id, versions).await?;
+                    }
                 }
                 return Ok(());
             }
 
-            // Get the ticket , this is RW for now
-            // dangerous mostly, but whatever
-            Command::GetShareTicket => {
+            // A call from the http api , always answered
+            Command::Api(call, reply) => {
+                let answer = self.api_call(call).await;
+                let _ = reply.send(answer);
+                return Ok(());
+            }
+
+            // Commands run in order , so this is a marker for the cli
+            Command::Ping => {
+                self.mess.pong().await?;
+                return Ok(());
+            }
+
+            // Stop syncing and close the stores cleanly
+            // the run loop exits after this.
+            Command::Shutdown => {
+                info!("shutting down");
+                // dropping them stops the sync tasks
+                self.open.clear();
+                self.api = None;
+                self.notes = None;
+                self.router.shutdown().await?;
+                return Ok(());
+            }
+
+            // Get a read or write ticket
+            // write is dangerous mostly, hand it out with care
+            Command::GetShareTicket(write) => {
                 if let Some(notes) = &self.notes {
-                    let share_ticket = notes.ticket();
+                    let share_ticket = notes.ticket(write).await?;
                     self.mess.share_ticket(share_ticket).await?;
                 }
                 return Ok(());
             }
 
             // Take the marked notes and actually delete the data.
+            // always snapshot first , so they can come back.
             Command::DeleteHidden => {
+                self.snapshot().await?;
                 if let Some(notes) = &self.notes {
                     notes.delete_hidden().await?;
                     self.mess.info("delete hidden").await?;
+                    self.mess.send_trash(Vec::new()).await?;
+                }
+                return Ok(());
+            }
+
+            // Hidden notes for the trash view
+            Command::GetTrash => {
+                if let Some(notes) = &self.notes {
+                    let trash = notes.trash().await?;
+                    self.mess.send_trash(trash).await?;
+                }
+                return Ok(());
+            }
+
+            // Back out of the trash
+            Command::RestoreNote(id) => {
+                if let Some(notes) = &self.notes {
+                    notes.restore_note(id.clone()).await?;
+                    self.mess.good(format!("restored {id}").as_str()).await?;
+                    self.mess.send_trash(notes.trash().await?).await?;
+                    let note_list = notes.get_note_vec().await;
+                    self.mess.send_note_list(note_list).await?;
+                }
+                self.reindex_note(id).await;
+                return Ok(());
+            }
+
+            // Gone for good , a snapshot first like delete hidden
+            Command::DeleteForever(id) => {
+                self.snapshot().await?;
+                if let Some(notes) = &self.notes {
+                    let others = notes.delete_forever(id.clone()).await?;
+                    let text = match others {
+                        0 => format!("deleted {id}"),
+                        n => format!("deleted your copy of {id} , {n} other authors still have one"),
+                    };
+                    self.mess.info(text.as_str()).await?;
+                    self.mess.send_trash(notes.trash().await?).await?;
                 }
                 return Ok(());
             }
@@ -327,15 +616,806 @@ impl Worker {
                 if let Some(notes) = &self.notes {
                     // notes.delete_note(id).await?;
                     notes.set_delete(id.clone()).await?;
-                    let info = notes.get_note(id).await?;
-                    println!("{:#?}", info);
                     self.mess.info("hide note").await?;
                 }
+                self.reindex_note(id).await;
+                return Ok(());
+            }
+
+            // Hide or show , safe to send twice ( the cli and the api )
+            Command::SetHidden(id, hidden) => {
+                if let Some(notes) = &self.notes {
+                    notes.set_hidden(id.clone(), hidden).await?;
+                }
+                self.reindex_note(id).await;
+                return Ok(());
+            }
+
+            // Revision list for the history view
+            Command::GetHistory(id) => {
+                if let Some(notes) = &self.notes {
+                    let revisions = notes.history(id.clone()).await?;
+                    self.mess.send_history(id, revisions).await?;
+                }
+                return Ok(());
+            }
+
+            // Grab an old version of a note
+            Command::GetRevision(id, hash) => {
+                if let Some(notes) = &self.notes {
+                    let note = notes.get_revision(id, hash).await?;
+                    self.mess.send_revision(hash, note).await?;
+                }
+                return Ok(());
+            }
+
+            // Empty folder for the tree
+            Command::NewFolder(path) => {
+                if let Some(notes) = &self.notes {
+                    notes.create_folder(path).await?;
+                    let note_list = notes.get_note_vec().await;
+                    self.mess.send_note_list(note_list).await?;
+                }
+                return Ok(());
+            }
+
+            // Move or rename a folder , re-keys everything under it
+            Command::MoveFolder(from, to) => {
+                if let Some(notes) = &self.notes {
+                    let count = notes.move_folder(from.clone(), to.clone()).await?;
+                    self.mess
+                        .good(format!("moved {} notes from {} to {}", count, from, to).as_str())
+                        .await?;
+                    let note_list = notes.get_note_vec().await;
+                    self.mess.send_note_list(note_list).await?;
+                }
+                self.reindex_all().await?;
+                return Ok(());
+            }
+
+            // New name , same note
+            Command::RenameNote(from, to) => {
+                if let Some(notes) = &self.notes {
+                    let to = notes.rename_note(from.clone(), to).await?;
+                    self.mess
+                        .good(format!("renamed {} to {}", from, to).as_str())
+                        .await?;
+                    let note_list = notes.get_note_vec().await;
+                    self.mess.send_note_list(note_list).await?;
+                    let note = notes.get_note(to).await?;
+                    self.mess.send_note(note).await?;
+                }
+                // the old id resolves to the new one
+                self.reindex_note(from).await;
+                return Ok(());
+            }
+
+            // Make a plain note collaborative
+            Command::ConvertNote(id) => {
+                if let Some(notes) = &self.notes {
+                    notes.convert_to_crdt(id.clone()).await?;
+                    self.mess.good("note is now collaborative").await?;
+                    let note = notes.get_note(id).await?;
+                    self.mess.send_note(note).await?;
+                }
+                return Ok(());
+            }
+
+            // A remote peer changed a note ( from the subscription )
+            // every open doc merges , only the shown one tells the gui
+            Command::RemoteChange(key, id) => {
+                let Some(open) = self.open.get(&key) else {
+                    return Ok(());
+                };
+                if open.notes.merge_remote(id.clone()).await? {
+                    info!("merged remote edits into {}", id);
+                }
+                // the history behind it may need fetching , off the loop
+                // and the conflict list may have moved
+                let notes = open.notes.clone();
+                let history_id = id.clone();
+                let command_tx = self.command_tx.clone();
+                self.tasks.push(Box::pin(async move {
+                    if let Err(e) = notes.keep_history(history_id.clone()).await {
+                        warn!("history not kept , {e}");
+                    }
+                    match notes.check_conflict(&history_id).await {
+                        Ok(true) => {
+                            let _ = command_tx.send(Command::GetNotes).await;
+                        }
+                        Ok(false) => {}
+                        Err(e) => warn!("conflict check for {history_id} failed , {e}"),
+                    }
+                }));
+                if let Some(api) = &self.api {
+                    api.notify(serde_json::json!({ "doc": key, "id": id }));
+                }
+                if self.config.doc_key.as_ref() == Some(&key) {
+                    self.mess.remote_change(id.clone()).await?;
+                    self.reindex_note(id).await;
+                }
+                return Ok(());
+            }
+
+            // Merged text from the conflict screen
+            Command::ResolveConflict(id, text, heads) => {
+                if let Some(notes) = &self.notes {
+                    notes.resolve_conflict(id.clone(), text, heads).await?;
+                    self.mess.good("conflict resolved").await?;
+                    let note = notes.get_note(id.clone()).await?;
+                    self.mess.send_note(note).await?;
+                    let conflicts = notes.get_conflicts().await?;
+                    self.mess.send_conflicts(conflicts).await?;
+                }
+                self.reindex_note(id).await;
+                return Ok(());
+            }
+
+            // Put an old version back as the newest revision
+            Command::RestoreRevision(id, hash) => {
+                if let Some(notes) = &self.notes {
+                    notes.restore_revision(id.clone(), hash).await?;
+                    self.mess.good("revision restored").await?;
+                    let note = notes.get_note(id.clone()).await?;
+                    self.mess.send_note(note).await?;
+                }
+                self.reindex_note(id).await;
+                return Ok(());
+            }
+
+            // Store a file and hand back the markdown for it
+            Command::AttachFile(path) => {
+                if let Some(notes) = &self.notes {
+                    let hash = notes.add_attachment(&path).await?;
+                    let name = path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_else(|| hash.to_string());
+                    self.mess.good(format!("attached {}", name).as_str()).await?;
+                    self.mess.attached(attachment_markdown(&name, &hash)).await?;
+                }
+                return Ok(());
+            }
+
+            // Image bytes for the viewer
+            Command::GetAttachment(url) => {
+                if let Some(notes) = &self.notes {
+                    let (hash, _) = parse_blob_url(&url).ok_or(anyhow!("bad attachment {url}"))?;
+                    let bytes = notes.get_attachment(hash).await?;
+                    self.mess.send_attachment(url, bytes).await?;
+                }
+                return Ok(());
+            }
+
+            // Save a clicked attachment into the download folder
+            Command::SaveAttachment(url) => {
+                if let Some(notes) = &self.notes {
+                    let (hash, name) =
+                        parse_blob_url(&url).ok_or(anyhow!("bad attachment {url}"))?;
+                    let bytes = notes.get_attachment(hash).await?;
+                    let dir = self.config.download_path.clone();
+                    tokio::fs::create_dir_all(&dir).await?;
+                    // no sneaking out of the download folder
+                    let name = std::path::Path::new(&name)
+                        .file_name()
+                        .context("attachment has no file name")?
+                        .to_owned();
+                    let path = dir.join(name);
+                    tokio::fs::write(&path, bytes).await?;
+                    self.mess
+                        .good(format!("saved {}", path.display()).as_str())
+                        .await?;
+                }
+                return Ok(());
+            }
+
+            // Write every note out to the download folder
+            // or a folder of your choosing , always written
+            Command::Export(to) => {
+                // cloned , the config gets saved at the end
+                if let Some(notes) = self.notes.clone() {
+                    self.mess.info("exporting notes").await?;
+                    let (hash, collection) = notes.bounce_down(false).await?;
+                    let key = hash.to_string();
+                    let doc_key = self.config.doc_key.clone().unwrap_or_default();
+                    if to.is_none() && self.config.last_exports.get(&doc_key) == Some(&key) {
+                        self.mess.info("nothing changed since the last export").await?;
+                        return Ok(());
+                    }
+                    let dir = match &to {
+                        Some(dir) => dir.clone(),
+                        None => self
+                            .config
+                            .download_path
+                            .join(Local::now().format("%Y-%m-%d").to_string()),
+                    };
+                    let total = collection.len();
+                    for (count, (name, blob)) in collection.iter().enumerate() {
+                        let Some(path) = note_path(&dir, name) else {
+                            self.mess.error(format!("skipped {name}").as_str()).await?;
+                            continue;
+                        };
+                        if let Some(parent) = path.parent() {
+                            tokio::fs::create_dir_all(parent).await?;
+                        }
+                        let bytes = notes.blob(*blob).await?;
+                        tokio::fs::write(&path, bytes).await?;
+                        if (count + 1) % PROGRESS_EVERY == 0 {
+                            self.mess
+                                .info(format!("exported {}/{}", count + 1, total).as_str())
+                                .await?;
+                        }
+                    }
+                    notes.tag_snapshot(hash).await?;
+                    self.mess
+                        .good(format!("exported {} notes to {}", total, dir.display()).as_str())
+                        .await?;
+                    if to.is_none() {
+                        self.config.last_exports.insert(doc_key, key);
+                        self.save_config().await?;
+                    }
+                }
+                return Ok(());
+            }
+
+            // Pull a folder of markdown files in
+            // existing notes are left alone unless overwrite is on.
+            Command::Import(dir, overwrite) => {
+                if let Some(notes) = &self.notes {
+                    self.mess
+                        .info(format!("scanning {}", dir.display()).as_str())
+                        .await?;
+                    let scan = tokio::task::spawn_blocking(move || import::scan(&dir)).await??;
+                    for (path, reason) in scan.skipped.iter().take(MAX_SKIP_REPORT) {
+                        self.mess
+                            .error(format!("skipped {} , {}", path.display(), reason).as_str())
+                            .await?;
+                    }
+                    if scan.skipped.len() > MAX_SKIP_REPORT {
+                        let more = scan.skipped.len() - MAX_SKIP_REPORT;
+                        self.mess
+                            .error(format!("... and {} more skipped", more).as_str())
+                            .await?;
+                    }
+                    // front matter we have no field for , say so rather than lose it quietly
+                    for (path, keys) in scan.dropped.iter().take(MAX_SKIP_REPORT) {
+                        let keys = keys.join(", ");
+                        let text = format!("{} , front matter not kept , {}", path.display(), keys);
+                        self.mess.info(text.as_str()).await?;
+                    }
+                    if scan.dropped.len() > MAX_SKIP_REPORT {
+                        let more = scan.dropped.len() - MAX_SKIP_REPORT;
+                        let text = format!("... and {} more with front matter not kept", more);
+                        self.mess.info(text.as_str()).await?;
+                    }
+                    let total = scan.notes.len();
+                    let mut imported = 0;
+                    let mut existing = 0;
+                    for (count, note) in scan.notes.into_iter().enumerate() {
+                        if notes.import_note(note, overwrite).await? {
+                            imported += 1;
+                        } else {
+                            existing += 1;
+                        }
+                        if (count + 1) % PROGRESS_EVERY == 0 {
+                            self.mess
+                                .info(format!("imported {}/{}", count + 1, total).as_str())
+                                .await?;
+                        }
+                    }
+                    self.mess
+                        .good(format!("imported {} notes", imported).as_str())
+                        .await?;
+                    if existing > 0 {
+                        let text = format!("{} already existed and were left alone", existing);
+                        self.mess.info(text.as_str()).await?;
+                    }
+                    let note_list = notes.get_note_vec().await;
+                    self.mess.send_note_list(note_list).await?;
+                }
+                self.reindex_all().await?;
+                return Ok(());
+            }
+
+            // Pick up edits made in the mirror folder
+            Command::MirrorScan => {
+                let Some(notes) = self.notes.clone() else {
+                    return Ok(());
+                };
+                // a read only doc is only mirrored out
+                if !notes.is_writable() {
+                    return Ok(());
+                }
+                let Some(mirror) = self.mirror.as_mut() else {
+                    return Ok(());
+                };
+                let changed = mirror.scan().await?;
+                if changed.is_empty() {
+                    return Ok(());
+                }
+                // one bad file should not hold up the rest
+                for edit in changed {
+                    let id = edit.id.clone();
+                    if let Err(e) = notes.update_note(id.clone(), edit.text.clone()).await {
+                        warn!("mirror file for {id} not taken in , {e}");
+                        self.mess
+                            .error(format!("{id} from the mirror folder not saved , {e}").as_str())
+                            .await?;
+                        if let Some(mirror) = self.mirror.as_mut() {
+                            mirror.failed(&edit);
+                        }
+                        continue;
+                    }
+                    if let Some(mirror) = self.mirror.as_mut() {
+                        mirror.synced(&edit);
+                    }
+                    self.mess
+                        .info(format!("{id} changed in the mirror folder").as_str())
+                        .await?;
+                    self.reindex_note(id.clone()).await;
+                    // open in the editor , reload it like a remote edit
+                    self.mess.remote_change(id).await?;
+                }
+                let note_list = notes.get_note_vec().await;
+                self.mess.send_note_list(note_list).await?;
+                return Ok(());
+            }
+
+            // The scheduler poking , snapshot if one is due
+            Command::SnapshotTick => {
+                let minutes = self.config.snapshot_minutes as i64;
+                if minutes > 0 && Utc::now().timestamp() - self.last_snapshot >= minutes * 60 {
+                    self.snapshot().await?;
+                }
+                if Utc::now().timestamp() - self.last_purge >= PURGE_EVERY {
+                    self.purge_trash().await?;
+                }
+                return Ok(());
+            }
+
+            // What changed between two snapshots , no new one is now
+            Command::DiffSnapshots(old, new) => {
+                if let Some(notes) = &self.notes {
+                    let old_notes = notes.snapshot_notes(notes.find_snapshot(&old).await?).await?;
+                    let (new_notes, new) = match new {
+                        Some(new) => {
+                            let hash = notes.find_snapshot(&new).await?;
+                            (notes.snapshot_notes(hash).await?, new)
+                        }
+                        None => (notes.current_notes().await?, String::from("now")),
+                    };
+                    let changes = diff_notes(&old_notes, &new_notes);
+                    self.mess.send_diff(format!("{old} -> {new}"), changes).await?;
+                }
+                return Ok(());
+            }
+
+            // What changed between two revisions of a note , no new one is current
+            Command::DiffRevisions(id, old, new) => {
+                if let Some(notes) = &self.notes {
+                    let old_note = notes.get_revision(id.clone(), old).await?;
+                    let mut new_note = match new {
+                        Some(new) => notes.get_revision(id.clone(), new).await?,
+                        None => notes.get_note(id.clone()).await?,
+                    };
+                    // renamed since , still the same note
+                    new_note.id = old_note.id.clone();
+                    let title = format!(
+                        "{id} : {} -> {}",
+                        old.fmt_short(),
+                        new.map(|h| h.fmt_short().to_string()).unwrap_or(String::from("now"))
+                    );
+                    let changes = diff_notes(&[old_note], &[new_note]);
+                    self.mess.send_diff(title, changes).await?;
+                }
+                return Ok(());
+            }
+
+            // Snapshots for the recovery view
+            Command::GetSnapshots => {
+                if let Some(notes) = &self.notes {
+                    let snapshots = notes.list_snapshots().await?;
+                    self.mess.send_snapshots(snapshots).await?;
+                }
+                return Ok(());
+            }
+
+            // Look inside a snapshot
+            Command::GetSnapshot(hash) => {
+                if let Some(notes) = &self.notes {
+                    let snapshot = notes.snapshot_notes(hash).await?;
+                    self.mess.send_snapshot(hash, snapshot).await?;
+                }
+                return Ok(());
+            }
+
+            // Put notes back from a snapshot
+            Command::RestoreSnapshot(hash, ids) => {
+                if let Some(notes) = &self.notes {
+                    let (restored, skipped) = notes.bounce_up(hash, ids).await?;
+                    self.mess
+                        .good(format!("restored {} notes", restored).as_str())
+                        .await?;
+                    if skipped > 0 {
+                        self.mess
+                            .error(format!("{} notes could not be restored", skipped).as_str())
+                            .await?;
+                    }
+                    let note_list = notes.get_note_vec().await;
+                    self.mess.send_note_list(note_list).await?;
+                }
+                self.reindex_all().await?;
+                return Ok(());
+            }
+
+            // Full text search
+            Command::Search(query) => {
+                let hits = self.search.search(&query, SEARCH_LIMIT);
+                self.mess.send_search(query, hits).await?;
                 return Ok(());
             }
         }
     }
 
+    // The note list and the conflicts for the gui
+    async fn send_notes(&self) -> Result<()> {
+        if let Some(notes) = &self.notes {
+            let note_list = notes.get_note_vec().await;
+            self.mess.send_note_list(note_list).await?;
+            let conflicts = notes.get_conflicts().await?;
+            self.mess.send_conflicts(conflicts).await?;
+        }
+        Ok(())
+    }
+
+    // -----
+    // Search and backlink index upkeep
+    // -----
+
+    // Build the whole index from scratch
+    // the bodies come in a few at a time , most of the wait is the blob store.
+    async fn reindex_all(&mut self) -> Result<()> {
+        self.search.clear();
+        self.links.clear();
+        let mut mirrored = Vec::new();
+        if let Some(notes) = self.notes.clone() {
+            for (from, to) in notes.get_redirects().await? {
+                self.links.moved(&from, &to);
+            }
+            let ids: Vec<String> = notes
+                .get_records()
+                .await?
+                .into_iter()
+                .map(|record| record.id)
+                .filter(|id| !is_folder(id))
+                .collect();
+            let mut fetched = n0_future::stream::iter(ids.into_iter().map(|id| {
+                let notes = notes.clone();
+                async move {
+                    let note = notes.get_note(id.clone()).await;
+                    (id, note)
+                }
+            }))
+            .buffered_ordered(REINDEX_FETCHES);
+            while let Some((id, note)) = fetched.next().await {
+                // a body that can't be fetched yet will come in on a remote change
+                match note {
+                    Ok(note) => {
+                        self.search.index(&note.id, &note.text);
+                        self.links.index(&note.id, &note.text);
+                        if !note.is_delete && !note.is_read_only() {
+                            mirrored.push(note);
+                        }
+                    }
+                    Err(e) => warn!("not indexed {id} , {e}"),
+                }
+            }
+        }
+        info!("search index has {} notes", self.search.count());
+        if let Some(mirror) = self.mirror.as_mut() {
+            let conflicts = mirror.sync(&mirrored).await?;
+            for path in conflicts {
+                self.mess
+                    .error(format!("mirror conflict , see {}", path.display()).as_str())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    // One note changed , hidden or gone ones come out
+    // the note itself is saved by now , so a failure here is only logged.
+    async fn reindex_note(&mut self, id: String) {
+        if let Err(e) = self.try_reindex_note(id.clone()).await {
+            warn!("reindex of {id} failed , {e}");
+        }
+    }
+
+    async fn try_reindex_note(&mut self, id: String) -> Result<()> {
+        if let Some(notes) = &self.notes {
+            let note = notes.get_note(id.clone()).await?;
+            // renamed notes come back with the new id
+            if note.id != id {
+                self.search.remove(&id);
+                self.links.remove(&id);
+                self.links.moved(&id, &note.id);
+                if let Some(mirror) = self.mirror.as_mut() {
+                    mirror.remove(&id).await?;
+                }
+            }
+            if note.is_delete || note.is_missing() || is_folder(&note.id) {
+                self.search.remove(&note.id);
+                self.links.remove(&note.id);
+            } else {
+                self.search.index(&note.id, &note.text);
+                self.links.index(&note.id, &note.text);
+            }
+            if let Some(mirror) = self.mirror.as_mut() {
+                let gone = note.is_delete
+                    || note.is_missing()
+                    || note.is_read_only()
+                    || is_folder(&note.id);
+                if gone {
+                    mirror.remove(&note.id).await?;
+                } else if let Written::Conflict(path) = mirror.write(&note.id, &note.text).await? {
+                    self.mess
+                        .error(format!("mirror conflict , see {}", path.display()).as_str())
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // -----
+    // Mirror folder
+    // -----
+
+    // Turn the mirror on or off to match the config
+    // the folder lives in the download path.
+    async fn start_mirror(&mut self) -> Result<()> {
+        if !self.config.mirror {
+            if self.mirror.take().is_some() {
+                self.mess.info("mirror stopped").await?;
+            }
+            return Ok(());
+        }
+        if self.mirror.is_some() || self.notes.is_none() {
+            return Ok(());
+        }
+        // a folder per doc set
+        let base = self.config.download_path.join("mirror");
+        let name = match &self.config.doc_key {
+            Some(key) => match self.config.doc_set(key) {
+                Some(doc_set) => doc_set.name.clone(),
+                None => key.clone(),
+            },
+            None => return Ok(()),
+        };
+        let dir = note_path(&base, &name).unwrap_or(base);
+        tokio::fs::create_dir_all(&dir).await?;
+        self.mess
+            .info(format!("mirroring notes to {}", dir.display()).as_str())
+            .await?;
+        self.mirror = Some(Mirror::new(dir));
+        if !self.mirror_scheduled {
+            self.mirror_scheduled = true;
+            self.tasks
+                .push(Box::pin(mirror_schedule(self.command_tx.clone())));
+        }
+        Ok(())
+    }
+
+    // -----
+    // Http api
+    // -----
+
+    // Start , stop or restart the api to match the config
+    async fn start_api(&mut self) -> Result<()> {
+        if !self.config.api {
+            if self.api.take().is_some() {
+                self.mess.info("api stopped").await?;
+            }
+            return Ok(());
+        }
+        if self.config.api_token.is_empty() {
+            self.config.api_token = crate::api::new_token();
+            self.save_config().await?;
+        }
+        if let Some(api) = &self.api {
+            if api.port == self.config.api_port && api.token == self.config.api_token {
+                return Ok(());
+            }
+        }
+        // let go of the port before binding again
+        self.api = None;
+        let (api, server) = Api::start(
+            self.config.api_port,
+            self.config.api_token.clone(),
+            self.command_tx.clone(),
+        )
+        .await
+        .with_context(|| format!("api could not listen on port {}", self.config.api_port))?;
+        self.tasks.push(server);
+        self.api = Some(api);
+        self.mess
+            .info(format!("api on http://127.0.0.1:{}", self.config.api_port).as_str())
+            .await?;
+        Ok(())
+    }
+
+    // Answer the api , changes go through the same paths as the gui
+    async fn api_call(&mut self, call: ApiCall) -> Result<serde_json::Value, ApiError> {
+        let Some(notes) = self.notes.clone() else {
+            return Err(ApiError::new(503, "no doc set open"));
+        };
+        let writable = || {
+            self.check_writable()
+                .map_err(|e| ApiError::new(403, e.to_string()))
+        };
+        match call {
+            ApiCall::List => to_json(&notes.get_note_vec().await),
+            ApiCall::Get(id) => {
+                let id = note_id(&id)?;
+                let note = notes.get_note(id.clone()).await?;
+                if note.is_missing() {
+                    return Err(ApiError::new(404, format!("no note called {id}")));
+                }
+                to_json(&note)
+            }
+            ApiCall::Create(id, text) => {
+                let id = note_id(&id)?;
+                writable()?;
+                if !notes.get_note(id.clone()).await?.is_missing() {
+                    return Err(ApiError::new(409, format!("{id} is already there")));
+                }
+                notes.create(id.clone(), text).await?;
+                self.api_changed(&notes, id).await
+            }
+            ApiCall::Put(id, text) => {
+                let id = note_id(&id)?;
+                writable()?;
+                notes.update_note(id.clone(), text).await?;
+                self.api_changed(&notes, id).await
+            }
+            ApiCall::Hide(id) => {
+                let id = note_id(&id)?;
+                writable()?;
+                let note = notes.get_note(id.clone()).await?;
+                if note.is_missing() {
+                    return Err(ApiError::new(404, format!("no note called {id}")));
+                }
+                // already hidden is fine
+                notes.set_hidden(note.id.clone(), true).await?;
+                self.api_changed(&notes, note.id).await?;
+                Ok(serde_json::json!({ "id": id, "hidden": true }))
+            }
+        }
+    }
+
+    // Reindex and refresh the gui list , hands back the note
+    // ( not a remote change , that is for edits from peers )
+    async fn api_changed(&mut self, notes: &Notes, id: String) -> Result<serde_json::Value, ApiError> {
+        self.reindex_note(id.clone()).await;
+        self.mess.send_note_list(notes.get_note_vec().await).await?;
+        to_json(&notes.get_note(id).await?)
+    }
+
+    // -----
+    // Snapshots
+    // -----
+
+    // Start the scheduler in the task pool , once.
+    async fn schedule_snapshots(&mut self) {
+        // gc can start once it knows what to keep
+        self.refresh_protected().await;
+        if self.snapshots_scheduled {
+            return;
+        }
+        self.snapshots_scheduled = true;
+        self.tasks
+            .push(Box::pin(snapshot_schedule(self.command_tx.clone())));
+    }
+
+    // Bounce down into a snapshot , prune the old ones
+    // and tell the gc what is still needed.
+    // Every open doc , not just the one on show.
+    async fn snapshot(&mut self) -> Result<()> {
+        self.last_snapshot = Utc::now().timestamp();
+        for open in self.open.values() {
+            let notes = &open.notes;
+            if let Some(tag) = notes.snapshot().await? {
+                info!("snapshot {tag}");
+            }
+            let pruned = notes.prune_snapshots().await?;
+            if pruned > 0 {
+                info!("pruned {pruned} snapshots");
+            }
+        }
+        self.refresh_protected().await;
+        Ok(())
+    }
+
+    // Delete notes that have sat in the trash too long
+    // snapshot first so they can still come back.
+    async fn purge_trash(&mut self) -> Result<()> {
+        self.last_purge = Utc::now().timestamp();
+        let days = self.config.trash_days;
+        if days == 0 {
+            return Ok(());
+        }
+        let mut expired = Vec::new();
+        for open in self.open.values() {
+            if !open.notes.is_writable() {
+                continue;
+            }
+            for id in open.notes.expired_trash(days).await? {
+                expired.push((open.notes.clone(), id));
+            }
+        }
+        if expired.is_empty() {
+            return Ok(());
+        }
+        self.snapshot().await?;
+        for (notes, id) in expired.iter() {
+            let others = notes.delete_forever(id.clone()).await?;
+            if others > 0 {
+                warn!("{id} is still held by {others} other authors");
+            }
+        }
+        self.mess
+            .info(format!("purged {} notes from the trash", expired.len()).as_str())
+            .await?;
+        Ok(())
+    }
+
+    // Walk every doc for everything the gc has to keep
+    // if one fails or is not open the gc is held off until it works.
+    async fn refresh_protected(&mut self) {
+        let mut live = Some(HashSet::new());
+        let mut paused = None;
+        for doc_set in self.config.doc_sets.iter() {
+            let Some(open) = self.open.get(&doc_set.doc_key) else {
+                paused = Some(format!("{} is not open", doc_set.name));
+                live = None;
+                break;
+            };
+            match open.notes.live_blobs().await {
+                Ok(blobs) => {
+                    // linked attachments are safe now , the tag can go
+                    if let Err(e) = open.notes.release_attachments(&blobs).await {
+                        warn!("attachment tags , {e}");
+                    }
+                    live.get_or_insert_default().extend(blobs);
+                }
+                Err(e) => {
+                    paused = Some(format!("{} , {e}", doc_set.name));
+                    live = None;
+                    break;
+                }
+            }
+        }
+        if let Ok(mut protected) = self.protected.lock() {
+            *protected = live;
+        }
+        // the store only grows while this lasts , say so once
+        if paused != self.gc_paused {
+            let told = match &paused {
+                Some(why) => {
+                    warn!("gc paused , {why}");
+                    self.mess
+                        .error(format!("cleanup paused , {why}").as_str())
+                        .await
+                }
+                None => self.mess.info("cleanup running again").await,
+            };
+            if let Err(e) = told {
+                warn!("gc state not sent , {e}");
+            }
+            self.gc_paused = paused;
+        }
+    }
+
     // Config save, push the config up to app for file save
     async fn save_config(&mut self) -> Result<()> {
         // move the config up to the gui and save.
@@ -345,6 +1425,92 @@ impl Worker {
         Ok(())
     }
 
+    // -----
+    // Doc sets
+    // -----
+
+    // Old configs have a single doc , give it a name
+    async fn migrate_config(&mut self) -> Result<()> {
+        if !self.config.doc_sets.is_empty() {
+            return Ok(());
+        }
+        if let Some(key) = self.config.doc_key.clone() {
+            self.config.doc_sets.push(DocSet {
+                name: String::from("notes"),
+                doc_key: key,
+                mothership: self.config.mothership.take(),
+                read_only: false,
+            });
+            self.save_config().await?;
+        }
+        Ok(())
+    }
+
+    // Open a doc from the local store and start it syncing
+    async fn open_doc(&mut self, key: &str) -> Result<Notes> {
+        if let Some(open) = self.open.get(key) {
+            return Ok(open.notes.clone());
+        }
+        let id = NamespaceId::from_str(key)?;
+        let author_id = self.author().await?;
+        let notes = Notes::from_id(
+            id,
+            author_id,
+            self.blobs.clone(),
+            self.docs.clone(),
+            &self.endpoint,
+            self.protected.clone(),
+        )
+        .await?;
+        self.add_open(key.to_string(), notes.clone()).await?;
+        Ok(notes)
+    }
+
+    // Keep hold of an open doc , subscribe and get synced
+    async fn add_open(&mut self, key: String, notes: Notes) -> Result<()> {
+        let (stop, _) = async_channel::bounded(1);
+        self.open.insert(
+            key.clone(),
+            OpenDoc { notes, _stop: stop },
+        );
+        self.run_sync(&key).await?;
+        Ok(())
+    }
+
+    // Show a doc set , the indexes and the mirror follow it
+    async fn activate(&mut self, key: String) -> Result<()> {
+        let notes = self.open_doc(&key).await?;
+        // the store knows best , keep the gui in step
+        let read_only = !notes.is_writable();
+        if let Some(doc_set) = self.config.doc_sets.iter_mut().find(|d| d.doc_key == key) {
+            doc_set.read_only = read_only;
+        }
+        self.notes = Some(notes);
+        self.config.doc_key = Some(key);
+        self.mirror = None;
+        self.start_mirror().await?;
+        self.reindex_all().await?;
+        self.schedule_snapshots().await;
+        Ok(())
+    }
+
+    // Turn away changes to a doc joined with a read ticket
+    fn check_writable(&self) -> Result<()> {
+        if let Some(notes) = &self.notes {
+            if !notes.is_writable() {
+                bail!("this doc set is read only , ask for a write ticket to make changes");
+            }
+        }
+        Ok(())
+    }
+
+    async fn note_list(&self) -> Vec<String> {
+        match &self.notes {
+            Some(notes) => notes.get_note_vec().await,
+            None => Vec::new(),
+        }
+    }
+
     // Author maker
     // If the author does not exist make a fresh one.
     async fn author(&mut self) -> Result<AuthorId> {
@@ -360,28 +1526,26 @@ impl Worker {
         Ok(author)
     }
 
-    // Async task attachment
-    // TODO , this needs some love
-    // should watch the retry count and BUG out if needed.
-    async fn run_sync(
-        &mut self,
-        notes: Notes,
-        command_tx: async_channel::Sender<Command>,
-    ) -> Result<()> {
-        warn!("Start the sync task");
-        warn!("Retry {}", self.retry);
-        if let Some(peers) = &self.config.mothership {
-            notes.share(peers.clone()).await?;
-        }
-        let events = notes.doc_subscribe().await?;
-        let mess = self.mess.clone();
-        let attached = notes.attached().await;
-        warn!("attached? {}", attached);
-        self.tasks.push(Box::pin(subscription_events(
-            events, mess, command_tx, self.retry, attached,
+    // Start the sync supervisor for a doc
+    // one per doc , a new one replaces ( and stops ) the last.
+    async fn run_sync(&mut self, key: &str) -> Result<()> {
+        let peers = match self.config.doc_set(key) {
+            Some(doc_set) => doc_set.mothership.clone(),
+            None => None,
+        };
+        let Some(open) = self.open.get_mut(key) else {
+            return Ok(());
+        };
+        let (stop, stopped) = async_channel::bounded(1);
+        open._stop = stop;
+        self.tasks.push(Box::pin(sync::supervise(
+            key.to_string(),
+            open.notes.clone(),
+            peers,
+            self.mess.clone(),
+            self.command_tx.clone(),
+            stopped,
         )));
-        warn!("Task should be attached");
-        self.retry += 1;
         Ok(())
     }
 
@@ -403,77 +1567,85 @@ impl Worker {
     }
 }
 
-// Replica event runner
-// Weidly this needs to be it's own function outside the struct.
-// TODO , it needs more notify and bugout.
-async fn subscription_events(
-    events: impl Stream<Item = Result<LiveEvent>>,
-    mess: MessageOut,
-    command_tx: async_channel::Sender<Command>,
-    retry: u32,
-    attached: bool,
-) {
-    warn!("Starting Event Runner");
-    let mut timer = interval(Duration::from_secs(30));
-
-    // Retry logic.
-    let base: u64 = 2;
-    let sleep_time = Duration::from_secs(base.pow(retry));
-    warn!("{:?}", &sleep_time);
-
-    let retry_timer = tokio::time::sleep(sleep_time);
-
-    tokio::pin!(events);
-    tokio::pin!(retry_timer);
+// Only one worker can have the store open at a time
+// the os drops the lock if we go away without cleaning up.
+fn lock_store(path: &Path) -> Result<std::fs::File> {
+    std::fs::create_dir_all(path)?;
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path.join("lock"))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(std::fs::TryLockError::WouldBlock) => bail!(
+            "the store at {} is in use , close the gui (or daemon) first",
+            path.display()
+        ),
+        Err(std::fs::TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+// A name for a new doc set , numbered if it is taken
+fn doc_set_name(config: &Config, name: String) -> String {
+    let name = match name.trim() {
+        "" => String::from("notes"),
+        name => name.to_string(),
+    };
+    let taken = |n: &str| config.doc_sets.iter().any(|d| d.name == n);
+    if !taken(&name) {
+        return name;
+    }
+    let mut count = 2;
+    while taken(&format!("{name} {count}")) {
+        count += 1;
+    }
+    format!("{name} {count}")
+}
+
+// Snapshot scheduler , lives in the task pool
+// it just pokes the worker , the worker decides if one is due.
+async fn snapshot_schedule(command_tx: async_channel::Sender<Command>) {
+    let mut timer = interval(SNAPSHOT_CHECK);
     loop {
-        tokio::select! {
-            Some(event) = events.next() => {
-                let event = match event {
-                    Ok(event) => event,
-                    Err(err) => {
-                        mess.error(format!("{:#?}",err).as_str()).await.unwrap();
-                        break;
-                    },
-                };
-                match event {
-                    LiveEvent::InsertRemote{from: _, ref entry,content_status: _} => {
-                        warn!("remote entry => {:#?}",entry);
-                        // TODO push up into gui.
-                        // command_tx.send(Command::)
-                    }
-                    LiveEvent::SyncFinished( ref sync_event) => {
-                        match  &sync_event.result  {
-                            Ok(_) => {},
-                            Err(err) => {
-                                mess.error(format!("{:#?}",err).as_str()).await.unwrap();
-                                command_tx.send(Command::Attach).await.unwrap();
-                                break;
-                                // exit the loop and try to attach again.
-                            },
-                        };
-                    },
-                    // Finshed sync (maybe) , update the notes...
-                    LiveEvent::PendingContentReady => {
-                        mess.good("Content Ready").await.unwrap();
-                        command_tx.send(Command::GetNotes).await.unwrap();
-                    },
-                    // Unhandled event , janky
-                    _ => {}
-                }
-                // mess.good(format!("{:#?}",event).as_str()).await.unwrap();
-                warn!("{:#?}", &event);
-            },
-            _ = timer.tick() => {
-                warn!("tick");
-            }
-            _ = &mut retry_timer, if (!attached & (retry < 5)) => {
-                warn!("retry");
-                command_tx.send(Command::Attach).await.unwrap();
-                break;
-            }
+        timer.tick().await;
+        if command_tx.send(Command::SnapshotTick).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Mirror poller , lives in the task pool
+// scans are cheap when nothing changed , only file times are looked at.
+async fn mirror_schedule(command_tx: async_channel::Sender<Command>) {
+    let mut timer = interval(MIRROR_POLL);
+    loop {
+        timer.tick().await;
+        if command_tx.send(Command::MirrorScan).await.is_err() {
+            break;
         }
     }
-    error!("Event runner exited (BAD)");
+}
+
+// Gc protection , the notes set plus whatever the docs need.
+// Holds off until the worker has filled the set.
+fn gc_protect(protected: Protected, docs_cb: ProtectCb) -> ProtectCb {
+    Arc::new(move |live: &mut HashSet<Hash>| {
+        let ready = match protected.lock() {
+            Ok(set) => match set.as_ref() {
+                Some(set) => {
+                    live.extend(set.iter().copied());
+                    true
+                }
+                None => false,
+            },
+            Err(_) => false,
+        };
+        if !ready {
+            return Box::pin(async { ProtectOutcome::Abort });
+        }
+        docs_cb(live)
+    })
 }
 
 // ----------
@@ -508,17 +1680,20 @@ impl TimerTask {
             loop {
                 tokio::select! {
                     command  = incoming.recv() => {
-                       let command = command.unwrap() ;
+                       // the worker has gone , so has the timer
+                       let Ok(command) = command else { break };
                            info!("timer -- {:?}",command);
                        match command {
                         TimerCommands::Start => { start_time = Instant::now(); running = true;},
-                        TimerCommands::Reset => { running = false ; let _ = mess.reset_timer().await; } ,
+                        TimerCommands::Reset => { running = false ; if mess.reset_timer().await.is_err() { break; } } ,
                       };
                     }
                     _ = interval.tick() => {
                     if running {
                         let since = start_time.elapsed().as_secs();
-                        let _ = mess.tick(since).await;
+                        if mess.tick(since).await.is_err() {
+                            break;
+                        }
                     }
                     }
                 }